@@ -0,0 +1,227 @@
+//! Source config files and the entries they contain.
+
+use crate::error::{Error, Result};
+use crate::transform::Transform;
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SourceFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl SourceFormat {
+    /// Picks a format from the file extension, falling back to JSON
+    pub fn from_path(path: &str) -> SourceFormat {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("yaml") | Some("yml") => SourceFormat::Yaml,
+            Some("toml") => SourceFormat::Toml,
+            _ => SourceFormat::Json,
+        }
+    }
+}
+
+/// What an `env` source does when its variable is not set
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum Unset {
+    /// Fail with an error (the default)
+    Error,
+    /// Resolve to an empty string
+    Empty,
+    /// Resolve to the given fallback value
+    Default(String),
+}
+
+/// How surrounding whitespace is trimmed from a fetched value
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Trim {
+    /// Keep the value exactly as fetched
+    None,
+    /// Turn `\r\n` line endings into `\n`, then strip every trailing `\n` and
+    /// `\r`
+    Newline,
+    /// Turn `\r\n` line endings into `\n`, then strip all leading and
+    /// trailing whitespace
+    Whitespace,
+}
+
+/// What a `cmd` source does with output written to stderr by a command that
+/// exited successfully
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum StderrPolicy {
+    /// Treat any stderr output as an error
+    Fail,
+    /// Discard stderr output
+    Ignore,
+    /// Pass stderr output through to our stderr unchanged
+    Forward,
+    /// Print each stderr line to our stderr as a warning naming the key (the
+    /// default)
+    Warn,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+/// How to retrieve the value of a single key
+pub struct ConfigValueSource {
+    /// Name of the source the value comes from, such as `cmd` or `env`
+    pub source: String,
+    /// Command to run for `cmd` sources
+    pub exec: Option<String>,
+    /// Arguments passed to the command of a `cmd` source
+    pub args: Option<Vec<String>>,
+    /// Working directory for the command of a `cmd` source
+    pub cwd: Option<String>,
+    /// Environment variables set for the command of a `cmd` source
+    pub env: Option<BTreeMap<String, String>>,
+    /// The value of a `value` source; may reference other keys as `${KEY}`
+    pub value: Option<String>,
+    /// Environment variable to read; defaults to the key name
+    pub var: Option<String>,
+    /// What an `env` source does when its variable is not set
+    pub unset: Option<Unset>,
+    /// File to read for `file` sources
+    pub path: Option<String>,
+    /// JSON pointer or JSONPath selecting a field from the JSON output of a
+    /// `cmd` source or the JSON contents of a `file` source
+    pub extract: Option<String>,
+    /// How the fetched value is trimmed; `cmd` sources default to `newline`
+    pub trim: Option<Trim>,
+    /// Transforms applied in order to the trimmed value
+    pub transforms: Option<Vec<Transform>>,
+    /// Maximum number of bytes a `file` source may read
+    pub max_size: Option<u64>,
+    /// Seconds a `cmd` source may run before it is killed
+    pub timeout: Option<f64>,
+    /// What a `cmd` source does when a successful command writes to stderr
+    pub stderr: Option<StderrPolicy>,
+    /// Fields not listed above, for sources registered by library users
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub extra: Map<String, Value>,
+}
+
+impl ConfigValueSource {
+    /// Names of the fields set on this entry, as written in config files
+    pub fn set_fields(&self) -> Vec<&str> {
+        let fields = [
+            ("source", true),
+            ("exec", self.exec.is_some()),
+            ("args", self.args.is_some()),
+            ("cwd", self.cwd.is_some()),
+            ("env", self.env.is_some()),
+            ("value", self.value.is_some()),
+            ("var", self.var.is_some()),
+            ("unset", self.unset.is_some()),
+            ("path", self.path.is_some()),
+            ("extract", self.extract.is_some()),
+            ("trim", self.trim.is_some()),
+            ("transforms", self.transforms.is_some()),
+            ("maxSize", self.max_size.is_some()),
+            ("timeout", self.timeout.is_some()),
+            ("stderr", self.stderr.is_some()),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, is_set)| *is_set)
+            .map(|(field, _)| field)
+            .chain(self.extra.keys().map(String::as_str))
+            .collect()
+    }
+}
+
+/// The contents of a source config file: base entries plus optional named
+/// profiles that are overlaid on them
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(title = "get-config source config")]
+pub struct SourceFile {
+    /// Reference to this schema, for editors
+    #[serde(rename = "$schema", default)]
+    pub schema: Option<String>,
+    /// Named sets of entries that override the base entries when selected
+    #[serde(default)]
+    pub profiles: HashMap<String, HashMap<String, ConfigValueSource>>,
+    #[serde(flatten)]
+    pub entries: HashMap<String, ConfigValueSource>,
+}
+
+/// A config entry along with the source file that defined it
+#[derive(Debug)]
+pub struct Entry {
+    /// Path of the source file, noting the profile if the entry came from one
+    pub origin: String,
+    pub config: ConfigValueSource,
+}
+
+/// Parses each source file in order, letting later files override earlier ones.
+/// When a profile is given, its entries override the base entries of the file
+/// that defines it.
+pub fn load_sources(
+    paths: &[String],
+    format: Option<SourceFormat>,
+    profile: Option<&str>,
+) -> Result<HashMap<String, Entry>> {
+    let mut entries = HashMap::new();
+    let mut profile_found = false;
+    for path in paths {
+        let mut file = parse_config(path, format)?;
+        for (key, config) in file.entries {
+            let origin = path.clone();
+            entries.insert(key, Entry { origin, config });
+        }
+        let Some(name) = profile else {
+            continue;
+        };
+        if let Some(overrides) = file.profiles.remove(name) {
+            profile_found = true;
+            for (key, config) in overrides {
+                let origin = format!("{} (profile '{}')", path, name);
+                entries.insert(key, Entry { origin, config });
+            }
+        }
+    }
+    if let Some(name) = profile {
+        if !profile_found {
+            return Err(Error::ProfileNotFound {
+                profile: name.to_string(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+pub fn parse_config(path: &str, format: Option<SourceFormat>) -> Result<SourceFile> {
+    let read_error = |source| Error::ReadConfig {
+        path: path.to_string(),
+        source,
+    };
+    let input_file = File::open(path).map_err(read_error)?;
+    let mut reader = BufReader::new(input_file);
+    let input = match format.unwrap_or_else(|| SourceFormat::from_path(path)) {
+        SourceFormat::Json => serde_json::from_reader(reader).map_err(|error| error.to_string()),
+        SourceFormat::Yaml => serde_yaml::from_reader(reader).map_err(|error| error.to_string()),
+        SourceFormat::Toml => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).map_err(read_error)?;
+            toml::from_str(&text).map_err(|error| error.to_string())
+        }
+    };
+    input.map_err(|message| Error::ParseConfig {
+        path: path.to_string(),
+        message,
+    })
+}
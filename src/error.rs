@@ -0,0 +1,174 @@
+//! The error type returned throughout get-config.
+
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while loading source config files, resolving
+/// keys or writing output. Each variant belongs to a class of failure with its
+/// own process exit code; see [`Error::exit_code`].
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unable to read '{path}': {source}")]
+    ReadConfig {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Unable to parse '{path}': {message}")]
+    ParseConfig { path: String, message: String },
+    #[error("Profile '{profile}' not found in any source config")]
+    ProfileNotFound { profile: String },
+    #[error("Key '{key}' not found in source config")]
+    KeyNotFound { key: String },
+    #[error("Key '{key}' is invalid: {}", problems.join("; "))]
+    InvalidEntry { key: String, problems: Vec<String> },
+    #[error("Keys reference each other in a cycle: {}", keys.join(" -> "))]
+    ReferenceCycle {
+        /// The keys in the cycle, starting and ending with the same key
+        keys: Vec<String>,
+    },
+    #[error("Found {count} problem(s) in source config")]
+    Validation { count: usize },
+    #[error("Unable to run '{exec}' for key '{key}': {source}")]
+    CommandSpawn {
+        key: String,
+        exec: String,
+        source: std::io::Error,
+    },
+    #[error(
+        "Command for key '{key}' {}{}",
+        code.map_or("was terminated by a signal".to_string(), |code| format!("exited with code {}", code)),
+        stderr_suffix(stderr)
+    )]
+    CommandFailed {
+        key: String,
+        /// Exit code, or `None` if the command was killed by a signal
+        code: Option<i32>,
+        stderr: String,
+    },
+    #[error("Command for key '{key}' wrote to stderr{}", stderr_suffix(stderr))]
+    CommandStderr { key: String, stderr: String },
+    #[error("Command for key '{key}' timed out after {timeout:?}")]
+    CommandTimeout { key: String, timeout: Duration },
+    #[error(
+        "Plugin '{plugin}' failed for key '{key}': {message}{}",
+        metadata.as_ref().map_or(String::new(), |metadata| format!(" ({})", metadata))
+    )]
+    PluginFailed {
+        key: String,
+        plugin: String,
+        message: String,
+        metadata: Option<serde_json::Value>,
+    },
+    #[error("Plugin '{plugin}' sent an invalid response for key '{key}': {message}")]
+    PluginResponse {
+        key: String,
+        plugin: String,
+        message: String,
+    },
+    #[error("Environment variable '{var}' for key '{key}' is not set")]
+    EnvNotSet { key: String, var: String },
+    #[error("Environment variable '{var}' for key '{key}' is not valid unicode")]
+    EnvNotUnicode { key: String, var: String },
+    #[error("File '{path}' for key '{key}' does not exist")]
+    FileNotFound { key: String, path: String },
+    #[error("Unable to read '{path}' for key '{key}': {source}")]
+    FileRead {
+        key: String,
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("File '{path}' for key '{key}' exceeds the maximum size of {limit} bytes")]
+    FileTooLarge {
+        key: String,
+        path: String,
+        limit: u64,
+    },
+    #[error("Value for key '{key}' is not valid UTF-8")]
+    NotUtf8 { key: String },
+    #[error("Unable to extract a field for key '{key}': {message}")]
+    Extract { key: String, message: String },
+    #[error("Transform '{transform}' failed for key '{key}': {message}")]
+    Transform {
+        key: String,
+        transform: &'static str,
+        message: String,
+    },
+    #[error("Unable to format key '{key}': {message}")]
+    Format { key: String, message: String },
+    #[error("Unable to run '{program}': {source}")]
+    Exec {
+        program: String,
+        source: std::io::Error,
+    },
+    #[error(
+        "{} keys failed to resolve:{}",
+        errors.len(),
+        errors.iter().map(|error| format!("\n  {}", error)).collect::<String>()
+    )]
+    Multiple { errors: Vec<Error> },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+impl Error {
+    /// The process exit code for this class of error:
+    ///
+    /// | Code | Class |
+    /// | --- | --- |
+    /// | 1 | Any other error |
+    /// | 3 | A source config file could not be read or parsed, or the profile doesn't exist |
+    /// | 4 | A requested key is not in the source config |
+    /// | 5 | An entry is invalid, entries reference each other in a cycle, or `validate` found problems |
+    /// | 6 | A command or plugin failed to start, exited unsuccessfully or wrote to stderr with `"stderr": "fail"`, or a plugin reported an error |
+    /// | 7 | A command timed out |
+    /// | 8 | An environment variable or file could not be read, or a field could not be extracted from a value or transformed |
+    /// | 9 | A value can't be written in the requested output format |
+    /// | 126 | The `exec` command could not be run |
+    /// | 127 | The `exec` command was not found |
+    ///
+    /// When several keys fail, the code is that of the first failure. Exit code
+    /// 2 is left to command line usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::ReadConfig { .. }
+            | Error::ParseConfig { .. }
+            | Error::ProfileNotFound { .. } => 3,
+            Error::KeyNotFound { .. } => 4,
+            Error::InvalidEntry { .. }
+            | Error::ReferenceCycle { .. }
+            | Error::Validation { .. } => 5,
+            Error::CommandSpawn { .. }
+            | Error::CommandFailed { .. }
+            | Error::CommandStderr { .. }
+            | Error::PluginFailed { .. }
+            | Error::PluginResponse { .. } => 6,
+            Error::CommandTimeout { .. } => 7,
+            Error::EnvNotSet { .. }
+            | Error::EnvNotUnicode { .. }
+            | Error::FileNotFound { .. }
+            | Error::FileRead { .. }
+            | Error::FileTooLarge { .. }
+            | Error::NotUtf8 { .. }
+            | Error::Extract { .. }
+            | Error::Transform { .. } => 8,
+            Error::Format { .. } => 9,
+            Error::Exec { source, .. } if source.kind() == std::io::ErrorKind::NotFound => 127,
+            Error::Exec { .. } => 126,
+            Error::Multiple { errors } => errors.first().map_or(1, Error::exit_code),
+            Error::Io(_) | Error::Json(_) => 1,
+        }
+    }
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    let stderr = stderr.trim_end();
+    if stderr.trim().is_empty() {
+        String::new()
+    } else {
+        format!(": {}", stderr)
+    }
+}
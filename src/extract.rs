@@ -0,0 +1,75 @@
+//! Extraction of a single field from a JSON value.
+//!
+//! An entry's `extract` field is either a JSON pointer such as
+//! `/SecretString`, or a JSONPath query such as `$.fields[?@.id == 'password'].value`
+//! that must match exactly one node. Strings are extracted without quotes,
+//! while other values are written as compact JSON.
+
+use crate::config::ConfigValueSource;
+use crate::error::{Error, Result};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+/// A parsed `extract` expression
+enum Expression {
+    Pointer(String),
+    Path(JsonPath),
+}
+
+fn parse(expression: &str) -> Result<Expression, String> {
+    if expression.is_empty() || expression.starts_with('/') {
+        Ok(Expression::Pointer(expression.to_string()))
+    } else if expression.starts_with('$') {
+        JsonPath::parse(expression)
+            .map(Expression::Path)
+            .map_err(|error| format!("invalid JSONPath '{}': {}", expression, error))
+    } else {
+        Err(format!(
+            "invalid extract '{}', expected a JSON pointer starting with '/' or a JSONPath starting with '$'",
+            expression
+        ))
+    }
+}
+
+/// Describes a malformed `extract` expression in an entry
+pub fn problems(config: &ConfigValueSource) -> Vec<String> {
+    config
+        .extract
+        .as_deref()
+        .and_then(|expression| parse(expression).err())
+        .into_iter()
+        .collect()
+}
+
+/// Parses `text` as JSON and returns the field selected by `expression`
+pub fn extract(key: &str, text: &str, expression: &str) -> Result<String> {
+    let error = |message: String| Error::Extract {
+        key: key.to_string(),
+        message,
+    };
+    let expression = parse(expression).map_err(error)?;
+    let document: Value = serde_json::from_str(text)
+        .map_err(|source| error(format!("value is not valid JSON: {}", source)))?;
+    let selected = match &expression {
+        Expression::Pointer(pointer) => document
+            .pointer(pointer)
+            .ok_or_else(|| error(format!("no value at '{}'", pointer)))?,
+        Expression::Path(path) => {
+            let nodes = path.query(&document);
+            match nodes.len() {
+                1 => nodes.all()[0],
+                0 => return Err(error("JSONPath matched nothing".to_string())),
+                count => {
+                    return Err(error(format!(
+                        "JSONPath matched {} values, expected one",
+                        count
+                    )))
+                }
+            }
+        }
+    };
+    match selected {
+        Value::String(value) => Ok(value.clone()),
+        value => Ok(value.to_string()),
+    }
+}
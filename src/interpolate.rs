@@ -0,0 +1,171 @@
+//! Interpolation of other keys and environment variables into entries.
+//!
+//! The `value`, `exec` and `args` fields of an entry may contain `${KEY}`, which
+//! is replaced with the resolved value of another key, and `${env:NAME}`, which
+//! is replaced with the environment variable `NAME`. `$$` stands for a literal
+//! `$`, and a `$` that isn't followed by `{` or `$` is kept as-is.
+
+use crate::config::ConfigValueSource;
+use crate::error::{Error, Result};
+
+/// A piece of an interpolated string
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Key(&'a str),
+    Env(&'a str),
+}
+
+/// Splits a string into literal text and references
+fn parse(text: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(index) = rest.find('$') {
+        let after = &rest[index + 1..];
+        if let Some(escaped) = after.strip_prefix('$') {
+            parts.push(Part::Text(&rest[..index + 1]));
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            parts.push(Part::Text(&rest[..index]));
+            let end = reference
+                .find('}')
+                .ok_or_else(|| "unterminated '${'".to_string())?;
+            let name = &reference[..end];
+            let part = match name.strip_prefix("env:") {
+                Some(var) => Part::Env(var),
+                None => Part::Key(name),
+            };
+            if matches!(part, Part::Key("") | Part::Env("")) {
+                return Err(format!("empty reference '${{{}}}'", name));
+            }
+            parts.push(part);
+            rest = &reference[end + 1..];
+        } else {
+            parts.push(Part::Text(&rest[..index + 1]));
+            rest = after;
+        }
+    }
+    parts.push(Part::Text(rest));
+    parts.retain(|part| *part != Part::Text(""));
+    Ok(parts)
+}
+
+/// The fields of an entry that are interpolated, by name
+fn fields(config: &ConfigValueSource) -> Vec<(&'static str, &str)> {
+    let mut fields = Vec::new();
+    if let Some(value) = &config.value {
+        fields.push(("value", value.as_str()));
+    }
+    if let Some(exec) = &config.exec {
+        fields.push(("exec", exec.as_str()));
+    }
+    for arg in config.args.iter().flatten() {
+        fields.push(("args", arg.as_str()));
+    }
+    fields
+}
+
+/// Describes each malformed reference in an entry
+pub fn problems(config: &ConfigValueSource) -> Vec<String> {
+    fields(config)
+        .into_iter()
+        .filter_map(|(field, text)| {
+            let message = parse(text).err()?;
+            Some(format!("{} in field '{}'", message, field))
+        })
+        .collect()
+}
+
+/// The keys an entry references, in the order they appear and without
+/// duplicates
+pub fn references(key: &str, config: &ConfigValueSource) -> Result<Vec<String>> {
+    let mut references: Vec<String> = Vec::new();
+    for (_, text) in fields(config) {
+        for part in parse(text).map_err(|_| invalid(key, problems(config)))? {
+            if let Part::Key(name) = part {
+                if !references.iter().any(|reference| reference == name) {
+                    references.push(name.to_string());
+                }
+            }
+        }
+    }
+    Ok(references)
+}
+
+/// Returns a copy of the entry with every reference replaced. `lookup` gives
+/// the value of a referenced key, or `None` if there is no such key.
+pub fn interpolate<'a>(
+    key: &str,
+    config: &ConfigValueSource,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Result<ConfigValueSource> {
+    let expand = |text: &str| -> Result<String> {
+        let parts = parse(text).map_err(|_| invalid(key, problems(config)))?;
+        let mut expanded = String::new();
+        for part in parts {
+            match part {
+                Part::Text(text) => expanded.push_str(text),
+                Part::Key(name) => expanded.push_str(lookup(name).ok_or_else(|| {
+                    invalid(key, vec![format!("references unknown key '{}'", name)])
+                })?),
+                Part::Env(var) => match std::env::var(var) {
+                    Ok(value) => expanded.push_str(&value),
+                    Err(std::env::VarError::NotPresent) => {
+                        return Err(Error::EnvNotSet {
+                            key: key.to_string(),
+                            var: var.to_string(),
+                        })
+                    }
+                    Err(std::env::VarError::NotUnicode(_)) => {
+                        return Err(Error::EnvNotUnicode {
+                            key: key.to_string(),
+                            var: var.to_string(),
+                        })
+                    }
+                },
+            }
+        }
+        Ok(expanded)
+    };
+    let mut config = config.clone();
+    config.value = config.value.as_deref().map(expand).transpose()?;
+    config.exec = config.exec.as_deref().map(expand).transpose()?;
+    if let Some(args) = &config.args {
+        config.args = Some(
+            args.iter()
+                .map(|arg| expand(arg))
+                .collect::<Result<Vec<String>>>()?,
+        );
+    }
+    Ok(config)
+}
+
+fn invalid(key: &str, problems: Vec<String>) -> Error {
+    Error::InvalidEntry {
+        key: key.to_string(),
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_splits_references_from_text() {
+        assert_eq!(
+            parse("postgres://${DB_HOST}:$$5432/${env:USER}$").unwrap(),
+            vec![
+                Part::Text("postgres://"),
+                Part::Key("DB_HOST"),
+                Part::Text(":$"),
+                Part::Text("5432/"),
+                Part::Env("USER"),
+                Part::Text("$"),
+            ]
+        );
+        assert!(parse("${DB_HOST").is_err());
+        assert!(parse("${}").is_err());
+        assert!(parse("${env:}").is_err());
+    }
+}
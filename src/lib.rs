@@ -0,0 +1,34 @@
+//! Retrieves configuration values from any source.
+//!
+//! Source config files map keys to entries describing where each value comes
+//! from. Load them with [`load_sources`] or [`parse_config`], then resolve keys
+//! with a [`Resolver`] or [`get_config_value`] and write the results with one of
+//! the formatters in [`output`].
+//!
+//! ```no_run
+//! use get_config::{output::output_dotenv, Resolver};
+//!
+//! let resolver = Resolver::load(&["config.json".to_string()], None, None)?;
+//! let values = resolver.resolve_all(&["AWS_REGION".to_string()])?;
+//! print!("{}", output_dotenv(&values)?);
+//! # Ok::<(), get_config::Error>(())
+//! ```
+
+pub mod config;
+pub mod error;
+pub mod extract;
+pub mod interpolate;
+pub mod output;
+pub mod resolve;
+pub mod source;
+pub mod transform;
+pub mod validate;
+
+pub use config::{
+    load_sources, parse_config, ConfigValueSource, Entry, SourceFile, SourceFormat, StderrPolicy,
+    Trim, Unset,
+};
+pub use error::{Error, Result};
+pub use resolve::{get_config_value, ResolveOptions, Resolver};
+pub use source::{CommandCache, ConfigSource, SourceContext, SourceRegistry};
+pub use transform::Transform;
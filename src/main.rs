@@ -1,113 +1,380 @@
-use clap::Parser;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use clap::{Parser, Subcommand, ValueEnum};
+use get_config::output::{
+    output_cmd, output_dotenv, output_fish, output_json, output_powershell, output_sh, OutputFormat,
+};
+use get_config::{validate, Error, ResolveOptions, Resolver, Result, SourceFormat, SourceRegistry};
+use std::num::NonZeroUsize;
+use std::process::{Command, ExitCode};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 /// CLI tool for retrieving configuration values from any source
 struct Args {
-    /// List of keys to retrieve
-    key_list: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    /// Which source config file to use
-    #[arg(short, long)]
-    source: String,
+    #[command(flatten)]
+    get: GetArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Print resolved values; this is the default when no subcommand is given
+    Get(GetArgs),
+    /// Run a command with the resolved values added to its environment
+    Exec(ExecArgs),
+    /// List the keys in the source config along with their source kinds
+    List(ListArgs),
+    /// Show the definition of a key without resolving it
+    Describe(DescribeArgs),
+    /// Check every entry in the source config files for problems
+    Validate(ValidateArgs),
+    /// Print the JSON Schema describing source config files
+    Schema,
+}
+
+/// Arguments for printing resolved values, the default mode
+#[derive(clap::Args, Debug)]
+struct GetArgs {
+    #[command(flatten)]
+    keys: KeyArgs,
+
+    #[command(flatten)]
+    sources: SourceArgs,
+
+    #[command(flatten)]
+    resolve: ResolveArgs,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Dotenv)]
+    format: OutputFormat,
+
+    /// Sort output keys alphabetically instead of following the order of the key list
+    #[arg(long)]
+    sort: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExecArgs {
+    #[command(flatten)]
+    keys: KeyArgs,
+
+    #[command(flatten)]
+    sources: SourceArgs,
+
+    #[command(flatten)]
+    resolve: ResolveArgs,
+
+    /// Command to run, followed by its arguments
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    #[command(flatten)]
+    sources: SourceArgs,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = InspectFormat::Text)]
+    format: InspectFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct DescribeArgs {
+    /// Key to describe
+    key: String,
+
+    #[command(flatten)]
+    sources: SourceArgs,
 
     /// Output format
-    #[arg(short, long, default_value_t = String::from("dotenv"))]
-    format: String,
+    #[arg(short, long, value_enum, default_value_t = InspectFormat::Text)]
+    format: InspectFormat,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-enum Source {
-    Cmd,
-    Value,
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    /// Source config files to check
+    #[arg(short, long, required = true)]
+    source: Vec<String>,
+
+    /// Format of the source config file; detected from its extension by default
+    #[arg(long, value_enum)]
+    source_format: Option<SourceFormat>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ConfigValueSource {
-    source: Source,
-    exec: Option<String>,
-    args: Option<Vec<String>>,
-    value: Option<String>,
+/// Output formats for the `list` and `describe` subcommands
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum InspectFormat {
+    /// Human readable text
+    Text,
+    /// JSON for use by scripts
+    Json,
+}
+
+/// Arguments selecting which keys to resolve
+#[derive(clap::Args, Debug)]
+struct KeyArgs {
+    /// Comma separated list of keys to retrieve; `*` and `?` act as wildcards
+    // Optional so clap can build these arguments when a subcommand is used instead
+    #[arg(required_unless_present = "all")]
+    key_list: Option<String>,
+
+    /// Retrieve every key in the source config
+    #[arg(long, conflicts_with = "key_list")]
+    all: bool,
+
+    /// Keys or wildcard patterns to leave out
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+}
+
+/// Arguments controlling how source config files are loaded and resolved
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    /// Source config files to use; later files override earlier ones key by key
+    #[arg(short, long, required = true)]
+    source: Vec<String>,
+
+    /// Format of the source config file; detected from its extension by default
+    #[arg(long, value_enum)]
+    source_format: Option<SourceFormat>,
+
+    /// Profile to overlay on the base entries of each source file
+    #[arg(short, long, env = "GET_CONFIG_PROFILE")]
+    profile: Option<String>,
+}
+
+/// Arguments controlling how entries are resolved
+#[derive(clap::Args, Debug)]
+struct ResolveArgs {
+    /// Default timeout in seconds for `cmd` sources without their own `timeout`
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    cmd_timeout: Option<Duration>,
+
+    /// Print which source file each resolved key came from to stderr
+    #[arg(long)]
+    explain: bool,
+
+    /// Resolve up to N keys at once
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    let input_path = args.source;
-    let input = match parse_config(&input_path) {
-        Ok(map) => map,
+fn parse_seconds(seconds: &str) -> std::result::Result<Duration, String> {
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|_| "expected a number of seconds")?;
+    Duration::try_from_secs_f64(seconds).map_err(|error| error.to_string())
+}
+
+/// Exits with the code for the class of error that occurred, which is listed
+/// on `Error::exit_code`
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
         Err(error) => {
-            return Err(error.into());
+            eprintln!("Error: {}", error);
+            ExitCode::from(error.exit_code())
         }
-    };
-    let mut map = HashMap::new();
-    for key in args.key_list.split(",") {
-        if let Some(config) = input.get(key) {
-            let value = get_config_value(config)?;
-            map.insert(key, value);
-        } else {
-            return Err(format!("Key '{}' not found in source config", key).into());
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Some(Commands::Get(get)) => run_get(get),
+        Some(Commands::Exec(exec)) => run_exec(exec),
+        Some(Commands::List(list)) => run_list(list),
+        Some(Commands::Describe(describe)) => run_describe(describe),
+        Some(Commands::Validate(validate)) => run_validate(validate),
+        Some(Commands::Schema) => {
+            let schema = SourceRegistry::default().schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            Ok(())
         }
+        None => run_get(args.get),
+    }
+}
+
+fn run_get(args: GetArgs) -> Result<()> {
+    let mut values = resolve_keys(&args.keys, &args.sources, &args.resolve)?;
+    if args.sort {
+        values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
     }
 
-    let output = match args.format.as_str() {
-        "json" => output_json(&map)?,
-        "dotenv" => output_dotenv(&map)?,
-        _ => output_dotenv(&map)?,
+    let output = match args.format {
+        OutputFormat::Json => output_json(&values)?,
+        OutputFormat::Dotenv => output_dotenv(&values)?,
+        OutputFormat::Sh => output_sh(&values)?,
+        OutputFormat::Fish => output_fish(&values)?,
+        OutputFormat::Powershell => output_powershell(&values)?,
+        OutputFormat::Cmd => output_cmd(&values)?,
     };
     print!("{}", output);
     Ok(())
 }
 
-fn get_config_value(config: &ConfigValueSource) -> Result<String, Box<dyn std::error::Error>> {
-    match config.source {
-        Source::Cmd => {
-            let mut cmd = std::process::Command::new(config.exec.as_ref().unwrap());
-            cmd.args(config.args.as_ref().unwrap_or(&Vec::new()));
-            let output = cmd.output()?;
-            if !output.stderr.is_empty() {
-                return Err(format!(
-                    "Error running command: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                )
-                .into());
+/// Runs the command with the resolved values in its environment. On Unix the
+/// command replaces this process, so it receives signals directly and its exit
+/// status is the one the caller sees.
+fn run_exec(args: ExecArgs) -> Result<()> {
+    let values = resolve_keys(&args.keys, &args.sources, &args.resolve)?;
+    let (program, program_args) = args.command.split_first().expect("clap requires a command");
+    let mut cmd = Command::new(program);
+    cmd.args(program_args).envs(values);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let source = cmd.exec();
+        Err(Error::Exec {
+            program: program.clone(),
+            source,
+        })
+    }
+
+    #[cfg(not(unix))]
+    {
+        // Ctrl+C reaches every process on the console, so let the child decide
+        // how to handle it and keep waiting for its exit status
+        ctrlc::set_handler(|| {}).map_err(std::io::Error::other)?;
+        let status = cmd.status().map_err(|source| Error::Exec {
+            program: program.clone(),
+            source,
+        })?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+fn run_list(args: ListArgs) -> Result<()> {
+    let resolver = load_source_args(&args.sources)?;
+    let input = resolver.entries();
+    let mut keys: Vec<&String> = input.keys().collect();
+    keys.sort_unstable();
+    match args.format {
+        InspectFormat::Text => {
+            let width = keys.iter().map(|key| key.len()).max().unwrap_or(0);
+            for key in keys {
+                println!("{:width$}  {}", key, input[key].config.source);
             }
-            let value = String::from_utf8(output.stdout)?;
-            return Ok(value);
         }
-        Source::Value => {
-            return Ok(config.value.as_ref().unwrap_or(&"".to_string()).to_string());
+        InspectFormat::Json => {
+            let list: Vec<serde_json::Value> = keys
+                .into_iter()
+                .map(|key| {
+                    let entry = &input[key];
+                    serde_json::json!({
+                        "key": key,
+                        "source": entry.config.source,
+                        "origin": entry.origin,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&list)?);
         }
     }
+    Ok(())
 }
 
-fn parse_config(
-    path: &str,
-) -> Result<HashMap<String, ConfigValueSource>, Box<dyn std::error::Error>> {
-    let input_file = match File::open(path) {
-        Ok(file) => file,
-        Err(error) => {
-            return Err(error.into());
+fn run_describe(args: DescribeArgs) -> Result<()> {
+    let resolver = load_source_args(&args.sources)?;
+    let entry = resolver
+        .entry(&args.key)
+        .ok_or_else(|| Error::KeyNotFound {
+            key: args.key.clone(),
+        })?;
+    // Only show the fields the entry actually sets
+    let mut definition = serde_json::to_value(&entry.config)?;
+    if let Some(fields) = definition.as_object_mut() {
+        fields.retain(|_, value| !value.is_null());
+    }
+    match args.format {
+        InspectFormat::Text => {
+            println!("key: {}", args.key);
+            println!("origin: {}", entry.origin);
+            println!("source: {}", entry.config.source);
+            if let Some(fields) = definition.as_object() {
+                for (name, value) in fields.iter().filter(|(name, _)| *name != "source") {
+                    match value {
+                        serde_json::Value::String(text) => println!("{}: {}", name, text),
+                        _ => println!("{}: {}", name, value),
+                    }
+                }
+            }
         }
-    };
-    let reader = BufReader::new(input_file);
-    let input: HashMap<String, ConfigValueSource> = serde_json::from_reader(reader)?;
-    Ok(input)
+        InspectFormat::Json => {
+            let description = serde_json::json!({
+                "key": args.key,
+                "origin": entry.origin,
+                "definition": definition,
+            });
+            println!("{}", serde_json::to_string(&description)?);
+        }
+    }
+    Ok(())
+}
+
+fn run_validate(args: ValidateArgs) -> Result<()> {
+    let registry = SourceRegistry::default();
+    let mut problem_count = 0;
+    for path in &args.source {
+        let problems = validate::validate_file(path, args.source_format, &registry)?;
+        if problems.is_empty() {
+            println!("{}: OK", path);
+        }
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        problem_count += problems.len();
+    }
+    if problem_count > 0 {
+        return Err(Error::Validation {
+            count: problem_count,
+        });
+    }
+    Ok(())
 }
 
-fn output_json(map: &HashMap<&str, String>) -> Result<String, Box<dyn std::error::Error>> {
-    let json = serde_json::to_string(&map)?;
-    Ok(json)
+fn load_source_args(sources: &SourceArgs) -> Result<Resolver> {
+    Resolver::load(
+        &sources.source,
+        sources.source_format,
+        sources.profile.as_deref(),
+    )
 }
 
-fn output_dotenv(map: &HashMap<&str, String>) -> Result<String, Box<dyn std::error::Error>> {
-    let mut result = String::new();
-    for (key, value) in map.iter() {
-        result.push_str(&format!("{}={}\n", key, value));
+/// Loads the source config files and resolves each selected key
+fn resolve_keys(
+    keys: &KeyArgs,
+    sources: &SourceArgs,
+    resolve: &ResolveArgs,
+) -> Result<Vec<(String, String)>> {
+    let resolver = load_source_args(sources)?.with_options(ResolveOptions {
+        cmd_timeout: resolve.cmd_timeout,
+        jobs: resolve.jobs,
+    });
+    let patterns: Vec<&str> = match (&keys.key_list, keys.all) {
+        (_, true) => vec!["*"],
+        (Some(key_list), false) => key_list.split(',').collect(),
+        (None, false) => Vec::new(),
+    };
+    let exclude: Vec<&str> = keys.exclude.iter().map(String::as_str).collect();
+    let keys = resolver.select_keys(&patterns, &exclude);
+    let values = resolver.resolve_all(&keys)?;
+    if resolve.explain {
+        for key in &keys {
+            if let Some(entry) = resolver.entry(key) {
+                eprintln!("{} <- {}", key, entry.origin);
+            }
+        }
     }
-    Ok(result)
+    Ok(values)
 }
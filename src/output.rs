@@ -0,0 +1,302 @@
+//! Output formatters for resolved values.
+//!
+//! # Dotenv dialect
+//!
+//! `output_dotenv` writes one `KEY=value` line per key, quoting each value so
+//! that `dotenvy`, `python-dotenv` and `docker compose` all read it back
+//! unchanged:
+//!
+//! - Values made up only of ASCII letters, digits and `_-.,/:@%+` are written
+//!   bare, as is the empty value.
+//! - Values without `'`, `\`, `\n` or `\r` are wrapped in single quotes, which
+//!   every parser treats literally, so `$`, `#`, `"` and spaces are kept as-is.
+//! - Anything else is wrapped in double quotes, with `\` written as `\\`, `"`
+//!   as `\"` and a newline as `\n`, so multi-line values stay on one line.
+//!
+//! The parsers disagree on everything else, so values they can't share are
+//! rejected: values containing `\r`, which `python-dotenv` turns into `\n`;
+//! values containing `${`, which `python-dotenv` expands even in single quotes;
+//! and values that need double quotes and also contain `$`, which each parser
+//! expands or escapes differently inside double quotes.
+//!
+//! # Shell formats
+//!
+//! The `sh`, `fish`, `powershell` and `cmd` formats write one statement per key
+//! that sets and exports the variable in that shell, so the output can be
+//! evaluated directly. Keys must be valid variable names (`[A-Za-z_][A-Za-z0-9_]*`)
+//! for these formats.
+//!
+//! Every formatter writes keys in the order they are given.
+
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    /// A JSON object of keys to values
+    Json,
+    /// `KEY=value` lines
+    Dotenv,
+    /// `export` statements for POSIX shells such as bash and zsh
+    Sh,
+    /// `set -gx` statements for fish
+    Fish,
+    /// `$env:` assignments for PowerShell
+    Powershell,
+    /// `set` statements for a Windows cmd batch file
+    Cmd,
+}
+
+/// Serializes key/value pairs as a JSON object without reordering them
+struct OrderedMap<'a>(&'a [(String, String)]);
+
+impl Serialize for OrderedMap<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+pub fn output_json(values: &[(String, String)]) -> Result<String> {
+    let json = serde_json::to_string(&OrderedMap(values))?;
+    Ok(json)
+}
+
+pub fn output_dotenv(values: &[(String, String)]) -> Result<String> {
+    let mut result = String::new();
+    for (key, value) in values {
+        let quoted = quote_dotenv(value).ok_or_else(|| Error::Format {
+            key: key.clone(),
+            message: "dotenv cannot portably represent values containing a carriage return or '${', or a '$' together with a quote, backslash or newline".to_string(),
+        })?;
+        result.push_str(&format!("{}={}\n", key, quoted));
+    }
+    Ok(result)
+}
+
+pub fn output_sh(values: &[(String, String)]) -> Result<String> {
+    let mut result = String::new();
+    for (key, value) in values {
+        check_variable_name(key)?;
+        result.push_str(&format!("export {}={}\n", key, quote_sh(value)));
+    }
+    Ok(result)
+}
+
+pub fn output_fish(values: &[(String, String)]) -> Result<String> {
+    let mut result = String::new();
+    for (key, value) in values {
+        check_variable_name(key)?;
+        let quoted = value.replace('\\', "\\\\").replace('\'', "\\'");
+        result.push_str(&format!("set -gx {} '{}'\n", key, quoted));
+    }
+    Ok(result)
+}
+
+pub fn output_powershell(values: &[(String, String)]) -> Result<String> {
+    let mut result = String::new();
+    for (key, value) in values {
+        check_variable_name(key)?;
+        let mut quoted = String::new();
+        for c in value.chars() {
+            // PowerShell also treats typographic single quotes as quote characters
+            if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                quoted.push(c);
+            }
+            quoted.push(c);
+        }
+        result.push_str(&format!("$env:{} = '{}'\n", key, quoted));
+    }
+    Ok(result)
+}
+
+/// Writes `set "KEY=value"` lines meant to be run from a batch file, where
+/// `%%` stands for a literal `%`
+pub fn output_cmd(values: &[(String, String)]) -> Result<String> {
+    let mut result = String::new();
+    for (key, value) in values {
+        check_variable_name(key)?;
+        if value.contains(['"', '\n', '\r']) {
+            return Err(Error::Format {
+                key: key.clone(),
+                message: "cmd cannot represent values containing a quote or line break".to_string(),
+            });
+        }
+        result.push_str(&format!("set \"{}={}\"\n", key, value.replace('%', "%%")));
+    }
+    Ok(result)
+}
+
+fn check_variable_name(key: &str) -> Result<()> {
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Format {
+            key: key.to_string(),
+            message: "not a valid shell variable name".to_string(),
+        })
+    }
+}
+
+fn quote_sh(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quotes a value for dotenv output, or returns `None` if the parsers would
+/// read it back differently
+fn quote_dotenv(value: &str) -> Option<String> {
+    let is_bare = |c: char| c.is_ascii_alphanumeric() || "_-.,/:@%+".contains(c);
+    if value.chars().all(is_bare) {
+        return Some(value.to_string());
+    }
+    if value.contains('\r') || value.contains("${") {
+        return None;
+    }
+    if !value.contains(['\'', '\\', '\n']) {
+        return Some(format!("'{}'", value));
+    }
+    if value.contains('$') {
+        return None;
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Some(quoted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Formats each value as dotenv and parses it back with `dotenvy`. The same
+    /// values also read back unchanged with `python-dotenv`.
+    fn round_trip(values: &[&str]) {
+        let keys: Vec<String> = (0..values.len()).map(|i| format!("KEY_{}", i)).collect();
+        let pairs: Vec<(String, String)> = keys
+            .into_iter()
+            .zip(values.iter().map(|value| value.to_string()))
+            .collect();
+        let output = output_dotenv(&pairs).unwrap();
+        let parsed: HashMap<String, String> = dotenvy::from_read_iter(output.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|error| panic!("failed to parse {:?}: {}", output, error));
+        for (key, value) in pairs {
+            assert_eq!(parsed.get(&key), Some(&value), "output was {:?}", output);
+        }
+    }
+
+    #[test]
+    fn output_keeps_key_order() {
+        let values = vec![
+            ("B".to_string(), "2".to_string()),
+            ("A".to_string(), "1".to_string()),
+        ];
+        assert_eq!(output_json(&values).unwrap(), r#"{"B":"2","A":"1"}"#);
+        assert_eq!(output_dotenv(&values).unwrap(), "B=2\nA=1\n");
+    }
+
+    #[test]
+    fn shell_formats_quote_values() {
+        let values = vec![("A".to_string(), "it's $HOME\\".to_string())];
+        assert_eq!(output_sh(&values).unwrap(), "export A='it'\\''s $HOME\\'\n");
+        assert_eq!(
+            output_fish(&values).unwrap(),
+            "set -gx A 'it\\'s $HOME\\\\'\n"
+        );
+        assert_eq!(
+            output_powershell(&values).unwrap(),
+            "$env:A = 'it''s $HOME\\'\n"
+        );
+        assert_eq!(output_cmd(&values).unwrap(), "set \"A=it's $HOME\\\"\n");
+    }
+
+    #[test]
+    fn shell_formats_reject_invalid_names() {
+        let values = vec![("NOT-VALID".to_string(), "x".to_string())];
+        assert!(output_sh(&values).is_err());
+        assert!(output_powershell(&values).is_err());
+    }
+
+    #[test]
+    fn bare_values_are_unquoted() {
+        assert_eq!(quote_dotenv("us-east-1").as_deref(), Some("us-east-1"));
+        assert_eq!(
+            quote_dotenv("https://example.com/a,b@c").as_deref(),
+            Some("https://example.com/a,b@c")
+        );
+        assert_eq!(quote_dotenv("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn special_characters_use_single_quotes() {
+        assert_eq!(
+            quote_dotenv("Hello World").as_deref(),
+            Some("'Hello World'")
+        );
+        assert_eq!(quote_dotenv("a#b").as_deref(), Some("'a#b'"));
+        assert_eq!(
+            quote_dotenv("$HOME \"x\"").as_deref(),
+            Some("'$HOME \"x\"'")
+        );
+    }
+
+    #[test]
+    fn quotes_backslashes_and_newlines_use_double_quotes() {
+        assert_eq!(quote_dotenv("it's").as_deref(), Some("\"it's\""));
+        assert_eq!(quote_dotenv("a\\b").as_deref(), Some("\"a\\\\b\""));
+        assert_eq!(
+            quote_dotenv("line 1\nline 2").as_deref(),
+            Some("\"line 1\\nline 2\"")
+        );
+        assert_eq!(quote_dotenv("it's $5"), None);
+    }
+
+    #[test]
+    fn values_round_trip_through_dotenvy() {
+        round_trip(&[
+            "plain",
+            "",
+            "Hello World",
+            "  padded  ",
+            "a#not a comment",
+            "single ' quote",
+            "double \" quote",
+            "both ' and \"",
+            "$HOME and $PATH",
+            "back\\slash",
+            "line 1\nline 2\n",
+            "=leading equals",
+            "tab\tseparated",
+            "unicode ✓",
+        ]);
+    }
+
+    /// Values that `dotenvy`, `python-dotenv` and `docker compose` would read
+    /// back differently are rejected rather than written
+    #[test]
+    fn unportable_values_are_rejected() {
+        for value in [
+            "carriage\rreturn",
+            "${HOME}",
+            "it's $5",
+            "line\n$HOME",
+            "back\\slash $",
+        ] {
+            let values = vec![("KEY".to_string(), value.to_string())];
+            assert!(matches!(output_dotenv(&values), Err(Error::Format { .. })));
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Resolving entries to values.
+
+use crate::config::{load_sources, ConfigValueSource, Entry, SourceFormat};
+use crate::error::{Error, Result};
+use crate::interpolate::{interpolate, references};
+use crate::source::{CommandCache, SourceContext, SourceRegistry};
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Settings that apply to every entry being resolved
+#[derive(Debug, Default)]
+pub struct ResolveOptions {
+    /// Timeout for `cmd` sources without their own `timeout`
+    pub cmd_timeout: Option<Duration>,
+    /// How many keys may be resolved at once; `None` resolves one at a time
+    pub jobs: Option<NonZeroUsize>,
+}
+
+/// Resolves keys against the entries loaded from source config files
+#[derive(Debug)]
+pub struct Resolver {
+    entries: HashMap<String, Entry>,
+    options: ResolveOptions,
+    registry: SourceRegistry,
+}
+
+impl Resolver {
+    pub fn new(entries: HashMap<String, Entry>) -> Resolver {
+        Resolver {
+            entries,
+            options: ResolveOptions::default(),
+            registry: SourceRegistry::default(),
+        }
+    }
+
+    /// Loads the source config files, as `load_sources` does, into a resolver
+    pub fn load(
+        paths: &[String],
+        format: Option<SourceFormat>,
+        profile: Option<&str>,
+    ) -> Result<Resolver> {
+        Ok(Resolver::new(load_sources(paths, format, profile)?))
+    }
+
+    pub fn with_options(mut self, options: ResolveOptions) -> Resolver {
+        self.options = options;
+        self
+    }
+
+    /// Replaces the built-in sources with the given registry
+    pub fn with_registry(mut self, registry: SourceRegistry) -> Resolver {
+        self.registry = registry;
+        self
+    }
+
+    pub fn registry(&self) -> &SourceRegistry {
+        &self.registry
+    }
+
+    pub fn entries(&self) -> &HashMap<String, Entry> {
+        &self.entries
+    }
+
+    pub fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Resolves a single key, along with any keys it references
+    pub fn resolve(&self, key: &str) -> Result<String> {
+        let mut values = self.resolve_all(&[key.to_string()])?;
+        Ok(values.remove(0).1)
+    }
+
+    /// Resolves each key, returning the values in the order of `keys`. Keys
+    /// referenced by the entries are resolved first, and each key is only
+    /// resolved once, as is each distinct command. Each key starts as soon as
+    /// the keys it references are resolved, up to `jobs` keys at a time.
+    ///
+    /// Resolution carries on past failures so that every failing key is
+    /// reported; keys that can't be resolved, such as missing keys, keys
+    /// referencing unknown keys and keys in a cycle, are left out along with the
+    /// keys referencing them. A single failure is returned as-is and several as
+    /// [`Error::Multiple`], with the keys that couldn't be resolved first and
+    /// then the failed fetches in dependency order.
+    pub fn resolve_all(&self, keys: &[String]) -> Result<Vec<(String, String)>> {
+        let Plan {
+            order,
+            references,
+            mut errors,
+        } = self.plan(keys);
+        let jobs = self.options.jobs.map_or(1, NonZeroUsize::get);
+        let commands = CommandCache::default();
+
+        // Keys referencing each key, and how many of its references each key is
+        // still waiting for
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (key, names) in &references {
+            for name in names {
+                dependents.entry(name).or_default().push(key);
+            }
+        }
+        let state = Mutex::new(Schedule {
+            waiting: references
+                .iter()
+                .map(|(key, names)| (*key, names.len()))
+                .collect(),
+            ready: order
+                .iter()
+                .copied()
+                .filter(|key| references[key].is_empty())
+                .collect(),
+            running: 0,
+            values: HashMap::new(),
+            failures: Vec::new(),
+        });
+        let changed = Condvar::new();
+
+        let resolve = |key: &str, values: &HashMap<&str, String>| -> Result<String> {
+            // Check the entry as written, since interpolated values are data
+            let config = &self.entries[key].config;
+            self.registry.check(key, config)?;
+            let config = interpolate(key, config, |name| values.get(name).map(String::as_str))?;
+            let context = SourceContext {
+                key,
+                options: &self.options,
+                commands: &commands,
+            };
+            self.registry.fetch_value(&config, &context)
+        };
+        let work = || {
+            let mut schedule = state.lock().unwrap();
+            loop {
+                let Some(key) = schedule.ready.pop_front() else {
+                    if schedule.running == 0 {
+                        // Nothing is left that could make more keys ready
+                        changed.notify_all();
+                        return;
+                    }
+                    schedule = changed.wait(schedule).unwrap();
+                    continue;
+                };
+                schedule.running += 1;
+                let values: HashMap<&str, String> = references[key]
+                    .iter()
+                    .map(|name| (*name, schedule.values[name].clone()))
+                    .collect();
+                drop(schedule);
+                let result = resolve(key, &values);
+                schedule = state.lock().unwrap();
+                schedule.running -= 1;
+                match result {
+                    Ok(value) => {
+                        schedule.values.insert(key, value);
+                        for dependent in dependents.get(key).into_iter().flatten() {
+                            let waiting = schedule.waiting.get_mut(dependent).unwrap();
+                            *waiting -= 1;
+                            if *waiting == 0 {
+                                schedule.ready.push_back(dependent);
+                            }
+                        }
+                    }
+                    // Keys referencing a failed key are never ready, since the
+                    // error already says why they can't be resolved
+                    Err(error) => schedule.failures.push((key, error)),
+                }
+                changed.notify_all();
+            }
+        };
+        thread::scope(|scope| {
+            for _ in 1..jobs.min(order.len()) {
+                scope.spawn(work);
+            }
+            work();
+        });
+
+        let Schedule {
+            values,
+            mut failures,
+            ..
+        } = state.into_inner().unwrap();
+        failures.sort_by_key(|(key, _)| order.iter().position(|ordered| ordered == key));
+        errors.extend(failures.into_iter().map(|(_, error)| error));
+        if errors.len() > 1 {
+            return Err(Error::Multiple { errors });
+        }
+        if let Some(error) = errors.pop() {
+            return Err(error);
+        }
+        Ok(keys
+            .iter()
+            .map(|key| (key.clone(), values[key.as_str()].clone()))
+            .collect())
+    }
+
+    /// Works out which of the given keys, and the keys they reference directly
+    /// or not, can be resolved. Each problem found rules out the key it belongs
+    /// to and every key referencing that key.
+    fn plan<'a>(&'a self, keys: &'a [String]) -> Plan<'a> {
+        let mut plan = Plan {
+            order: Vec::new(),
+            references: HashMap::new(),
+            errors: Vec::new(),
+        };
+        let mut visited = HashMap::new();
+        let mut path = Vec::new();
+        for key in keys {
+            if !self.entries.contains_key(key) {
+                plan.errors.push(Error::KeyNotFound { key: key.clone() });
+                continue;
+            }
+            self.visit(key, &mut path, &mut visited, &mut plan);
+        }
+        plan
+    }
+
+    /// Adds `key` to the plan after the keys it references, returning whether
+    /// it can be resolved. `path` holds the keys currently being visited, so
+    /// seeing one of them again means a cycle, and `visited` remembers the
+    /// answer for keys already visited.
+    fn visit<'a>(
+        &'a self,
+        key: &'a str,
+        path: &mut Vec<&'a str>,
+        visited: &mut HashMap<&'a str, bool>,
+        plan: &mut Plan<'a>,
+    ) -> bool {
+        if let Some(resolvable) = visited.get(key) {
+            return *resolvable;
+        }
+        if let Some(start) = path.iter().position(|visiting| *visiting == key) {
+            let mut keys: Vec<String> = path[start..].iter().map(|key| key.to_string()).collect();
+            keys.push(key.to_string());
+            plan.errors.push(Error::ReferenceCycle { keys });
+            return false;
+        }
+        let (key, entry) = self.entries.get_key_value(key).expect("key was checked");
+        let names = match references(key, &entry.config) {
+            Ok(names) => names,
+            Err(error) => {
+                plan.errors.push(error);
+                visited.insert(key, false);
+                return false;
+            }
+        };
+        path.push(key);
+        let mut resolvable = true;
+        let mut known = Vec::new();
+        let mut unknown = Vec::new();
+        for name in &names {
+            match self.entries.get_key_value(name) {
+                Some((name, _)) => {
+                    resolvable &= self.visit(name, path, visited, plan);
+                    known.push(name.as_str());
+                }
+                None => unknown.push(format!("references unknown key '{}'", name)),
+            }
+        }
+        path.pop();
+        if !unknown.is_empty() {
+            plan.errors.push(Error::InvalidEntry {
+                key: key.clone(),
+                problems: unknown,
+            });
+            resolvable = false;
+        }
+        visited.insert(key, resolvable);
+        if resolvable {
+            plan.order.push(key);
+            plan.references.insert(key, known);
+        }
+        resolvable
+    }
+
+    /// Expands key names and wildcard patterns into the keys to resolve, in the
+    /// order given and without duplicates. Keys matched by a pattern are added in
+    /// alphabetical order, and keys matching any `exclude` pattern are left out.
+    /// A key without wildcards is kept even if it isn't in the source config,
+    /// so that resolving it reports it as missing along with any other failure.
+    pub fn select_keys(&self, patterns: &[&str], exclude: &[&str]) -> Vec<String> {
+        let mut available: Vec<&str> = self.entries.keys().map(String::as_str).collect();
+        available.sort_unstable();
+        let mut selected: Vec<String> = Vec::new();
+        for pattern in patterns {
+            let matches: Vec<&str> = if pattern.contains(['*', '?']) {
+                available
+                    .iter()
+                    .copied()
+                    .filter(|key| wildcard_match(pattern, key))
+                    .collect()
+            } else {
+                vec![pattern]
+            };
+            for key in matches {
+                let excluded = exclude.iter().any(|exclude| wildcard_match(exclude, key));
+                if !excluded && !selected.iter().any(|selected| selected == key) {
+                    selected.push(key.to_string());
+                }
+            }
+        }
+        selected
+    }
+}
+
+/// The keys `resolve_all` can resolve, and why the others can't be
+struct Plan<'a> {
+    /// Resolvable keys, each after the keys it references
+    order: Vec<&'a str>,
+    /// The keys each resolvable key references
+    references: HashMap<&'a str, Vec<&'a str>>,
+    errors: Vec<Error>,
+}
+
+/// Progress of `resolve_all`, shared by the threads resolving keys
+struct Schedule<'a> {
+    /// How many references of each key are still unresolved
+    waiting: HashMap<&'a str, usize>,
+    /// Keys whose references are all resolved, in the order they became ready
+    ready: VecDeque<&'a str>,
+    /// How many keys are being resolved right now
+    running: usize,
+    values: HashMap<&'a str, String>,
+    failures: Vec<(&'a str, Error)>,
+}
+
+/// Matches `text` against a pattern where `*` matches any run of characters and
+/// `?` matches a single character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the most recent `*` if the current attempt fails
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Checks an entry, then fetches and trims its value using the built-in sources.
+/// Environment variables are interpolated, but a standalone entry can't
+/// reference other keys.
+pub fn get_config_value(
+    key: &str,
+    config: &ConfigValueSource,
+    options: &ResolveOptions,
+) -> Result<String> {
+    let registry = SourceRegistry::default();
+    registry.check(key, config)?;
+    let config = interpolate(key, config, |_| None)?;
+    let context = SourceContext {
+        key,
+        options,
+        commands: &CommandCache::default(),
+    };
+    registry.fetch_value(&config, &context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_handles_stars_and_question_marks() {
+        assert!(wildcard_match("DB_?OST", "DB_HOST"));
+        assert!(!wildcard_match("DB_?OST", "DB_OST"));
+        assert!(wildcard_match("*_*_URL", "APP_DB_URL"));
+        assert!(wildcard_match("**", "ANYTHING"));
+        assert!(!wildcard_match("*_*_URL", "APP_URL"));
+        // The first `b` is a false start, so the `*` has to take it back
+        assert!(wildcard_match("a*b", "aXbYb"));
+        assert!(!wildcard_match("a*b", "aXbYc"));
+        assert!(wildcard_match("", ""));
+        assert!(!wildcard_match("", "KEY"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("?", ""));
+    }
+}
@@ -0,0 +1,349 @@
+//! Source kinds and the registry that maps `source` names to them.
+//!
+//! The built-in sources are `cmd`, `value`, `env`, `file` and `plugin:<name>`,
+//! which runs an external `get-config-plugin-<name>` executable. Programs that
+//! embed get-config can add their own by implementing [`ConfigSource`] and
+//! registering it with a [`SourceRegistry`], which a
+//! [`Resolver`](crate::Resolver) then resolves entries with.
+
+mod cmd;
+mod env;
+mod file;
+mod plugin;
+mod value;
+
+pub use cmd::{CmdSource, CommandCache};
+pub use env::EnvSource;
+pub use file::FileSource;
+pub use plugin::{PluginRequest, PluginResponse, PluginSource, PLUGIN_PREFIX, PROTOCOL_VERSION};
+pub use value::ValueSource;
+
+use crate::config::{ConfigValueSource, SourceFile, Trim};
+use crate::error::{Error, Result};
+use crate::resolve::ResolveOptions;
+use crate::{extract, interpolate, transform};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Fields every entry may set, whatever its source
+const COMMON_FIELDS: &[&str] = &["source", "trim", "transforms"];
+
+/// What a source is given along with the entry it fetches
+pub struct SourceContext<'a> {
+    /// The key being resolved
+    pub key: &'a str,
+    pub options: &'a ResolveOptions,
+    /// Outputs of the commands already run while resolving the same keys
+    pub commands: &'a CommandCache,
+}
+
+/// A kind of source that entries can fetch their value from
+pub trait ConfigSource: Send + Sync {
+    /// Fields this source reads from an entry, besides `source`, `trim` and
+    /// `transforms`.
+    /// Fields that `ConfigValueSource` doesn't know end up in its `extra` map.
+    fn fields(&self) -> &[&str];
+
+    /// Whether an entry for this source may set the given field
+    fn accepts_field(&self, field: &str) -> bool {
+        self.fields().contains(&field)
+    }
+
+    /// Fields an entry for this source must set
+    fn required_fields(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Checks the values of an entry's fields, returning a description of each
+    /// problem found
+    fn problems(&self, _config: &ConfigValueSource) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// How the value is trimmed when the entry doesn't set `trim`
+    fn default_trim(&self) -> Trim {
+        Trim::None
+    }
+
+    /// Fetches the untrimmed value of an entry
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String>;
+}
+
+/// The sources entries can use, keyed by the name given in their `source` field.
+/// Sources registered under a prefix handle every name of the form
+/// `prefix:argument`, such as `plugin:vault`.
+#[derive(Clone)]
+pub struct SourceRegistry {
+    sources: HashMap<String, Arc<dyn ConfigSource>>,
+    prefixed: HashMap<String, Arc<dyn ConfigSource>>,
+}
+
+impl Default for SourceRegistry {
+    /// A registry of the built-in sources
+    fn default() -> SourceRegistry {
+        let mut registry = SourceRegistry::empty();
+        registry
+            .register("cmd", CmdSource)
+            .register("value", ValueSource)
+            .register("env", EnvSource)
+            .register("file", FileSource)
+            .register_prefix("plugin", PluginSource);
+        registry
+    }
+}
+
+impl fmt::Debug for SourceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl SourceRegistry {
+    /// A registry without any sources, not even the built-in ones
+    pub fn empty() -> SourceRegistry {
+        SourceRegistry {
+            sources: HashMap::new(),
+            prefixed: HashMap::new(),
+        }
+    }
+
+    /// Adds a source under the given name, replacing any source already using it
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        source: impl ConfigSource + 'static,
+    ) -> &mut SourceRegistry {
+        self.sources.insert(name.into(), Arc::new(source));
+        self
+    }
+
+    /// Adds a source for every name of the form `prefix:argument`. The source
+    /// can read the argument from the entry's `source` field.
+    pub fn register_prefix(
+        &mut self,
+        prefix: impl Into<String>,
+        source: impl ConfigSource + 'static,
+    ) -> &mut SourceRegistry {
+        self.prefixed.insert(prefix.into(), Arc::new(source));
+        self
+    }
+
+    /// Looks up a source by name, falling back to the sources registered by
+    /// prefix
+    pub fn get(&self, name: &str) -> Option<&dyn ConfigSource> {
+        let source = self.sources.get(name).or_else(|| {
+            let (prefix, argument) = name.split_once(':')?;
+            self.prefixed.get(prefix).filter(|_| !argument.is_empty())
+        });
+        source.map(|source| source.as_ref())
+    }
+
+    /// Names of the registered sources, in alphabetical order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.sources.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Prefixes of the sources registered by prefix, in alphabetical order
+    pub fn prefixes(&self) -> Vec<&str> {
+        let mut prefixes: Vec<&str> = self.prefixed.keys().map(String::as_str).collect();
+        prefixes.sort_unstable();
+        prefixes
+    }
+
+    /// Checks that an entry names a registered source, sets the fields that
+    /// source needs and none that it ignores, returning a description of each
+    /// problem found
+    pub fn problems(&self, config: &ConfigValueSource) -> Vec<String> {
+        let Some(source) = self.get(&config.source) else {
+            let mut expected = self.names();
+            let prefixes: Vec<String> = self
+                .prefixes()
+                .into_iter()
+                .map(|prefix| format!("{}:<name>", prefix))
+                .collect();
+            expected.extend(prefixes.iter().map(String::as_str));
+            return vec![format!(
+                "unknown source '{}', expected one of: {}",
+                config.source,
+                expected.join(", ")
+            )];
+        };
+        let mut problems = Vec::new();
+        let set_fields = config.set_fields();
+        for field in source.required_fields() {
+            if !set_fields.contains(field) {
+                problems.push(format!(
+                    "missing field '{}' required by '{}' sources",
+                    field, config.source
+                ));
+            }
+        }
+        for field in set_fields {
+            if COMMON_FIELDS.contains(&field) || source.accepts_field(field) {
+                continue;
+            }
+            let named = self.names().into_iter().map(|name| {
+                let source = &self.sources[name];
+                (format!("'{}'", name), source)
+            });
+            let prefixed = self.prefixes().into_iter().map(|prefix| {
+                let source = &self.prefixed[prefix];
+                (format!("'{}:<name>'", prefix), source)
+            });
+            let users: Vec<String> = named
+                .chain(prefixed)
+                .filter(|(_, source)| source.fields().contains(&field))
+                .map(|(name, _)| name)
+                .collect();
+            if users.is_empty() {
+                problems.push(format!("unknown field '{}'", field));
+            } else {
+                problems.push(format!(
+                    "field '{}' is only used by {} sources",
+                    field,
+                    users.join(", ")
+                ));
+            }
+        }
+        problems.extend(source.problems(config));
+        problems.extend(interpolate::problems(config));
+        problems.extend(extract::problems(config));
+        for transform in config.transforms.iter().flatten() {
+            problems.extend(transform.problem());
+        }
+        problems
+    }
+
+    /// Checks an entry, then fetches its value as `fetch_value` does
+    pub fn get_value(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        self.check(context.key, config)?;
+        self.fetch_value(config, context)
+    }
+
+    /// Returns the entry's problems, if it has any, as an invalid entry error
+    pub fn check(&self, key: &str, config: &ConfigValueSource) -> Result<()> {
+        let problems = self.problems(config);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(Error::InvalidEntry {
+            key: key.to_string(),
+            problems,
+        })
+    }
+
+    /// Fetches the value of an entry that has already been checked and
+    /// interpolated, extracts the field selected by `extract`, trims the result
+    /// and applies the entry's transforms. The entry isn't checked again, since
+    /// interpolated values may contain text such as `${` that only means
+    /// something before interpolation.
+    pub fn fetch_value(
+        &self,
+        config: &ConfigValueSource,
+        context: &SourceContext,
+    ) -> Result<String> {
+        let source = self
+            .get(&config.source)
+            .ok_or_else(|| Error::InvalidEntry {
+                key: context.key.to_string(),
+                problems: vec![format!("unknown source '{}'", config.source)],
+            })?;
+        let mut value = source.fetch(config, context)?;
+        if let Some(expression) = &config.extract {
+            value = extract::extract(context.key, &value, expression)?;
+        }
+        let value = trim_value(value, config.trim.unwrap_or_else(|| source.default_trim()));
+        transform::apply_all(
+            context.key,
+            value,
+            config.transforms.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// A JSON Schema for source config files whose entries may use any of the
+    /// registered sources
+    pub fn schema(&self) -> Value {
+        let mut schema = schemars::schema_for!(SourceFile).to_value();
+        let entry = &mut schema["$defs"]["ConfigValueSource"];
+        let mut names = vec![json!({ "enum": self.names() })];
+        for prefix in self.prefixes() {
+            names.push(json!({
+                "type": "string",
+                "pattern": format!("^{}:.+", prefix),
+            }));
+        }
+        entry["properties"]["source"] = json!({
+            "description": "Name of the source the value comes from",
+            "anyOf": names,
+        });
+        for source in self.sources.values().chain(self.prefixed.values()) {
+            for field in source.fields() {
+                let properties = entry["properties"].as_object_mut();
+                if let Some(properties) = properties {
+                    properties.entry(*field).or_insert_with(|| json!({}));
+                }
+            }
+        }
+        // Sources registered by prefix, like plugins, define their own fields,
+        // so only entries for named sources are limited to the known fields
+        let prefixes = self.prefixes();
+        if prefixes.is_empty() {
+            entry["additionalProperties"] = json!(false);
+        } else {
+            let known: Vec<&String> = entry["properties"]
+                .as_object()
+                .map(|properties| properties.keys().collect())
+                .unwrap_or_default();
+            let known = json!(known);
+            let prefixes: Vec<String> = prefixes.into_iter().map(regex::escape).collect();
+            entry["if"] = json!({
+                "properties": {
+                    "source": { "pattern": format!("^({}):", prefixes.join("|")) }
+                },
+                "required": ["source"],
+            });
+            entry["else"] = json!({ "propertyNames": { "enum": known } });
+        }
+        schema
+    }
+}
+
+/// The error for an entry missing a field its source requires. Entries are
+/// checked before they are resolved, so this only guards against misuse.
+fn missing_field(key: &str, field: &str) -> Error {
+    Error::InvalidEntry {
+        key: key.to_string(),
+        problems: vec![format!("missing field '{}'", field)],
+    }
+}
+
+/// Trims a value as the entry asks. Trimming also turns Windows `\r\n` line
+/// endings into `\n`, so values are the same on every platform.
+fn trim_value(value: String, trim: Trim) -> String {
+    match trim {
+        Trim::None => value,
+        Trim::Newline => value
+            .replace("\r\n", "\n")
+            .trim_end_matches(['\n', '\r'])
+            .to_string(),
+        Trim::Whitespace => value.replace("\r\n", "\n").trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_value_normalizes_line_endings_only_when_trimming() {
+        let value = || " one\r\ntwo \r\n\n\r".to_string();
+        assert_eq!(trim_value(value(), Trim::None), " one\r\ntwo \r\n\n\r");
+        assert_eq!(trim_value(value(), Trim::Newline), " one\ntwo ");
+        assert_eq!(trim_value(value(), Trim::Whitespace), "one\ntwo");
+        assert_eq!(trim_value("\n\n".to_string(), Trim::Newline), "");
+    }
+}
@@ -0,0 +1,400 @@
+use super::{missing_field, ConfigSource, SourceContext};
+use crate::config::{ConfigValueSource, StderrPolicy, Trim};
+use crate::error::{Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use wait_timeout::ChildExt;
+
+/// The stdout of the command given by the entry's `exec` and `args`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CmdSource;
+
+/// The output of every command run so far, so that entries running the same
+/// command with the same environment and working directory share one run
+#[derive(Debug, Default)]
+pub struct CommandCache {
+    outputs: Mutex<HashMap<CommandKey, Arc<Mutex<Option<Run>>>>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CommandKey {
+    exec: String,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    cwd: Option<String>,
+}
+
+/// A finished run of a command
+#[derive(Debug)]
+struct Run {
+    output: Output,
+    /// Whether an entry has already shown the command's stderr to the user
+    stderr_reported: bool,
+}
+
+impl CommandCache {
+    /// Returns the output of an earlier run of the same command, or else calls
+    /// `run` and remembers its output. Concurrent callers with the same command
+    /// wait for the first one instead of running it again. Failures to run the
+    /// command, including timeouts, aren't remembered.
+    ///
+    /// Also returns whether the run's stderr was already reported by a caller
+    /// that passed `reports_stderr`, so it's shown once however many entries
+    /// share the run.
+    fn output(
+        &self,
+        command: CommandKey,
+        reports_stderr: bool,
+        run: impl FnOnce() -> Result<Output>,
+    ) -> Result<(Output, bool)> {
+        let slot = self
+            .outputs
+            .lock()
+            .unwrap()
+            .entry(command)
+            .or_default()
+            .clone();
+        let mut cached = slot.lock().unwrap();
+        let cached = match cached.as_mut() {
+            Some(cached) => cached,
+            None => cached.insert(Run {
+                output: run()?,
+                stderr_reported: false,
+            }),
+        };
+        let reported = cached.stderr_reported;
+        cached.stderr_reported |= reports_stderr;
+        Ok((cached.output.clone(), reported))
+    }
+}
+
+impl ConfigSource for CmdSource {
+    fn fields(&self) -> &[&str] {
+        &["exec", "args", "cwd", "env", "timeout", "stderr", "extract"]
+    }
+
+    fn required_fields(&self) -> &[&str] {
+        &["exec"]
+    }
+
+    fn problems(&self, config: &ConfigValueSource) -> Vec<String> {
+        match config.timeout {
+            Some(timeout) if Duration::try_from_secs_f64(timeout).is_err() => {
+                vec![format!("invalid timeout {}", timeout)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn default_trim(&self) -> Trim {
+        // Command output almost always ends in a newline nobody wants in the value
+        Trim::Newline
+    }
+
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        let key = context.key;
+        let exec = config
+            .exec
+            .as_ref()
+            .ok_or_else(|| missing_field(key, "exec"))?;
+        let command = CommandKey {
+            exec: exec.clone(),
+            args: config.args.clone().unwrap_or_default(),
+            env: config.env.clone().unwrap_or_default(),
+            cwd: config.cwd.clone(),
+        };
+        let mut cmd = Command::new(exec);
+        cmd.args(&command.args).envs(&command.env);
+        if let Some(cwd) = &command.cwd {
+            cmd.current_dir(cwd);
+        }
+        let timeout = match config.timeout {
+            Some(seconds) => Some(parse_timeout(key, seconds)?),
+            None => context.options.cmd_timeout,
+        };
+        // The exit status decides success, so stderr only fails when asked to
+        let policy = config.stderr.unwrap_or(StderrPolicy::Warn);
+        let reports_stderr = matches!(policy, StderrPolicy::Forward | StderrPolicy::Warn);
+        let (output, reported) = context
+            .commands
+            .output(command, reports_stderr, || {
+                run_command(key, cmd, None, timeout)
+            })
+            .map_err(|error| match error {
+                Error::Io(source) => Error::CommandSpawn {
+                    key: key.to_string(),
+                    exec: exec.clone(),
+                    source,
+                },
+                error => error,
+            })?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                key: key.to_string(),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        // Warnings and forwarded stderr are only shown by the first entry to
+        // report them, but every entry sharing the run applies its own policy
+        if !(reports_stderr && reported) {
+            handle_stderr(key, &output.stderr, policy, &mut std::io::stderr())?;
+        }
+        String::from_utf8(output.stdout).map_err(|_| Error::NotUtf8 {
+            key: key.to_string(),
+        })
+    }
+}
+
+/// Applies an entry's stderr policy to the stderr of a successful command,
+/// writing forwarded stderr and warnings to `out`
+pub(super) fn handle_stderr(
+    key: &str,
+    stderr: &[u8],
+    policy: StderrPolicy,
+    out: &mut impl Write,
+) -> Result<()> {
+    if stderr.is_empty() {
+        return Ok(());
+    }
+    match policy {
+        StderrPolicy::Fail => Err(Error::CommandStderr {
+            key: key.to_string(),
+            stderr: String::from_utf8_lossy(stderr).into_owned(),
+        }),
+        StderrPolicy::Ignore => Ok(()),
+        StderrPolicy::Forward => {
+            out.write_all(stderr)?;
+            Ok(())
+        }
+        StderrPolicy::Warn => {
+            for line in String::from_utf8_lossy(stderr).lines() {
+                writeln!(out, "warning: {}: {}", key, line)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs a command, writing `input` to its stdin and killing it if it is still
+/// running once `timeout` elapses
+pub(super) fn run_command(
+    key: &str,
+    mut cmd: Command,
+    input: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> Result<Output> {
+    if input.is_none() && timeout.is_none() {
+        return Ok(cmd.output()?);
+    }
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let mut child = cmd
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut pipe)) = (input, child.stdin.take()) {
+        // Write from a thread so a child that replies before reading everything
+        // can't deadlock us. Dropping the pipe afterwards closes the child's stdin.
+        thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        });
+    }
+    // Drain both pipes while waiting so a chatty child can't block on a full pipe
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let status = match timeout {
+        None => child.wait()?,
+        Some(timeout) => match child.wait_timeout(timeout)? {
+            Some(status) => status,
+            None => {
+                child.kill()?;
+                child.wait()?;
+                return Err(Error::CommandTimeout {
+                    key: key.to_string(),
+                    timeout,
+                });
+            }
+        },
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+pub(super) fn parse_timeout(key: &str, seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| Error::InvalidEntry {
+        key: key.to_string(),
+        problems: vec![format!("invalid timeout {}", seconds)],
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::resolve::ResolveOptions;
+    use crate::source::SourceRegistry;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    /// An entry running a shell script, with any extra fields given
+    fn entry(script: &str, fields: Value) -> ConfigValueSource {
+        let mut entry = json!({ "source": "cmd", "exec": "sh", "args": ["-c", script] });
+        if let (Some(entry), Value::Object(fields)) = (entry.as_object_mut(), fields) {
+            entry.extend(fields);
+        }
+        serde_json::from_value(entry).unwrap()
+    }
+
+    fn fetch(config: &ConfigValueSource, options: &ResolveOptions) -> Result<String> {
+        let context = SourceContext {
+            key: "TOKEN",
+            options,
+            commands: &CommandCache::default(),
+        };
+        SourceRegistry::default().get_value(config, &context)
+    }
+
+    #[test]
+    fn slow_commands_are_killed_after_the_timeout() {
+        let start = Instant::now();
+        let config = entry("sleep 5", json!({ "timeout": 0.2 }));
+        let error = fetch(&config, &ResolveOptions::default()).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(
+            &error,
+            Error::CommandTimeout { key, timeout }
+                if key == "TOKEN" && *timeout == Duration::from_millis(200)
+        ));
+        assert_eq!(error.exit_code(), 7);
+    }
+
+    #[test]
+    fn entry_timeouts_override_the_default() {
+        let options = ResolveOptions {
+            cmd_timeout: Some(Duration::from_millis(200)),
+            ..ResolveOptions::default()
+        };
+        let error = fetch(&entry("sleep 5", json!({})), &options).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::CommandTimeout { timeout, .. } if timeout == Duration::from_millis(200)
+        ));
+        let config = entry("sleep 0.5; echo done", json!({ "timeout": 10 }));
+        assert_eq!(fetch(&config, &options).unwrap(), "done");
+    }
+
+    #[test]
+    fn failed_commands_report_their_exit_code_and_stderr() {
+        let config = entry("echo denied >&2; exit 3", json!({}));
+        let error = fetch(&config, &ResolveOptions::default()).unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::CommandFailed { key, code: Some(3), stderr }
+                if key == "TOKEN" && stderr == "denied\n"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Command for key 'TOKEN' exited with code 3: denied"
+        );
+        assert_eq!(error.exit_code(), 6);
+    }
+
+    #[test]
+    fn command_output_is_trimmed_as_asked() {
+        let options = ResolveOptions::default();
+        let script = "printf ' a\\r\\nb \\r\\n'";
+        assert_eq!(
+            fetch(&entry(script, json!({})), &options).unwrap(),
+            " a\nb "
+        );
+        let config = entry(script, json!({ "trim": "none" }));
+        assert_eq!(fetch(&config, &options).unwrap(), " a\r\nb \r\n");
+        let config = entry(script, json!({ "trim": "whitespace" }));
+        assert_eq!(fetch(&config, &options).unwrap(), "a\nb");
+    }
+
+    #[test]
+    fn stderr_policies() {
+        let stderr = b"slow disk\nretrying\n";
+        let handle = |policy| {
+            let mut out = Vec::new();
+            let result = handle_stderr("TOKEN", stderr, policy, &mut out);
+            (result, String::from_utf8(out).unwrap())
+        };
+        let (result, out) = handle(StderrPolicy::Fail);
+        assert!(matches!(
+            result,
+            Err(Error::CommandStderr { key, stderr })
+                if key == "TOKEN" && stderr == "slow disk\nretrying\n"
+        ));
+        assert_eq!(out, "");
+        let (result, out) = handle(StderrPolicy::Ignore);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        let (result, out) = handle(StderrPolicy::Forward);
+        assert!(result.is_ok());
+        assert_eq!(out, "slow disk\nretrying\n");
+        let (result, out) = handle(StderrPolicy::Warn);
+        assert!(result.is_ok());
+        assert_eq!(out, "warning: TOKEN: slow disk\nwarning: TOKEN: retrying\n");
+        assert!(handle_stderr("TOKEN", b"", StderrPolicy::Fail, &mut Vec::new()).is_ok());
+
+        let script = "echo value; echo noise >&2";
+        let options = ResolveOptions::default();
+        let config = entry(script, json!({ "stderr": "fail" }));
+        assert!(matches!(
+            fetch(&config, &options),
+            Err(Error::CommandStderr { .. })
+        ));
+        let config = entry(script, json!({ "stderr": "ignore" }));
+        assert_eq!(fetch(&config, &options).unwrap(), "value");
+    }
+
+    #[test]
+    fn shared_runs_report_stderr_once() {
+        let cache = CommandCache::default();
+        let runs = AtomicUsize::new(0);
+        let output = |reports_stderr| {
+            let command = CommandKey {
+                exec: "sh".to_string(),
+                args: vec!["-c".to_string(), "echo noise >&2".to_string()],
+                env: BTreeMap::new(),
+                cwd: None,
+            };
+            let (_, reported) = cache
+                .output(command, reports_stderr, || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(Command::new("sh").args(["-c", "echo noise >&2"]).output()?)
+                })
+                .unwrap();
+            reported
+        };
+        assert!(!output(false));
+        assert!(!output(true));
+        assert!(output(true));
+        assert!(output(false));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
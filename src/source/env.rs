@@ -0,0 +1,33 @@
+use super::{ConfigSource, SourceContext};
+use crate::config::{ConfigValueSource, Unset};
+use crate::error::{Error, Result};
+
+/// An environment variable, named by the entry's `var` field or else its key
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnvSource;
+
+impl ConfigSource for EnvSource {
+    fn fields(&self) -> &[&str] {
+        &["var", "unset"]
+    }
+
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        let key = context.key;
+        let var = config.var.as_deref().unwrap_or(key);
+        match std::env::var(var) {
+            Ok(value) => Ok(value),
+            Err(std::env::VarError::NotPresent) => match &config.unset {
+                None | Some(Unset::Error) => Err(Error::EnvNotSet {
+                    key: key.to_string(),
+                    var: var.to_string(),
+                }),
+                Some(Unset::Empty) => Ok(String::new()),
+                Some(Unset::Default(value)) => Ok(value.clone()),
+            },
+            Err(std::env::VarError::NotUnicode(_)) => Err(Error::EnvNotUnicode {
+                key: key.to_string(),
+                var: var.to_string(),
+            }),
+        }
+    }
+}
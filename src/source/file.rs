@@ -0,0 +1,71 @@
+use super::{missing_field, ConfigSource, SourceContext};
+use crate::config::ConfigValueSource;
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+/// The contents of the file at the entry's `path`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSource;
+
+impl ConfigSource for FileSource {
+    fn fields(&self) -> &[&str] {
+        &["path", "maxSize", "extract"]
+    }
+
+    fn required_fields(&self) -> &[&str] {
+        &["path"]
+    }
+
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        let path = config
+            .path
+            .as_ref()
+            .ok_or_else(|| missing_field(context.key, "path"))?;
+        read_value_file(context.key, path, config.max_size)
+    }
+}
+
+fn read_value_file(key: &str, path: &str, max_size: Option<u64>) -> Result<String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::FileNotFound {
+                key: key.to_string(),
+                path: path.to_string(),
+            });
+        }
+        Err(source) => {
+            return Err(Error::FileRead {
+                key: key.to_string(),
+                path: path.to_string(),
+                source,
+            });
+        }
+    };
+    let mut bytes = Vec::new();
+    let read = match max_size {
+        // Read one byte past the limit so oversized files can be detected
+        Some(limit) => file.take(limit.saturating_add(1)).read_to_end(&mut bytes),
+        None => BufReader::new(file).read_to_end(&mut bytes),
+    };
+    if let Err(source) = read {
+        return Err(Error::FileRead {
+            key: key.to_string(),
+            path: path.to_string(),
+            source,
+        });
+    }
+    if let Some(limit) = max_size {
+        if bytes.len() as u64 > limit {
+            return Err(Error::FileTooLarge {
+                key: key.to_string(),
+                path: path.to_string(),
+                limit,
+            });
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Error::NotUtf8 {
+        key: key.to_string(),
+    })
+}
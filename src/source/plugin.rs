@@ -0,0 +1,154 @@
+use super::cmd::{handle_stderr, parse_timeout, run_command};
+use super::{ConfigSource, SourceContext};
+use crate::config::{ConfigValueSource, StderrPolicy};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::Command;
+
+/// Version of the plugin protocol spoken by this build of get-config
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Prefix of the executable that implements a plugin
+pub const PLUGIN_PREFIX: &str = "get-config-plugin-";
+
+/// A value fetched by an external `get-config-plugin-<name>` executable, for
+/// entries whose source is `plugin:<name>`.
+///
+/// The plugin is sent a [`PluginRequest`] as JSON on stdin and must write a
+/// [`PluginResponse`] as JSON to stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PluginSource;
+
+/// What get-config sends a plugin
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PluginRequest {
+    /// Always [`PROTOCOL_VERSION`]
+    pub protocol: u32,
+    /// The key being resolved
+    pub key: String,
+    /// The entry as written in the source config, without unset fields
+    pub entry: Value,
+}
+
+/// What a plugin replies with: either a value, or an error with optional
+/// metadata describing it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PluginResponse {
+    /// The protocol version the plugin speaks, which must match the request
+    pub protocol: u32,
+    pub value: Option<String>,
+    pub error: Option<String>,
+    /// Extra details about an error, shown along with it
+    pub metadata: Option<Value>,
+}
+
+impl ConfigSource for PluginSource {
+    fn fields(&self) -> &[&str] {
+        &["timeout", "stderr"]
+    }
+
+    // Plugins define their own fields, so every field is passed through
+    fn accepts_field(&self, _field: &str) -> bool {
+        true
+    }
+
+    fn problems(&self, config: &ConfigValueSource) -> Vec<String> {
+        // The name becomes part of an executable looked up on PATH, so it must
+        // not be able to point at a path instead
+        let plugin = plugin_name(config);
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if plugin.chars().all(valid) {
+            return Vec::new();
+        }
+        vec![format!(
+            "invalid plugin name '{}', expected only letters, digits, '-' and '_'",
+            plugin
+        )]
+    }
+
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        let key = context.key;
+        let plugin = plugin_name(config);
+        let exec = format!("{}{}", PLUGIN_PREFIX, plugin);
+        let mut entry = serde_json::to_value(config)?;
+        if let Some(fields) = entry.as_object_mut() {
+            fields.retain(|_, value| !value.is_null());
+        }
+        let request = PluginRequest {
+            protocol: PROTOCOL_VERSION,
+            key: key.to_string(),
+            entry,
+        };
+        let timeout = match config.timeout {
+            Some(seconds) => Some(parse_timeout(key, seconds)?),
+            None => context.options.cmd_timeout,
+        };
+        let input = serde_json::to_vec(&request)?;
+        let output = run_command(key, Command::new(&exec), Some(input), timeout).map_err(
+            |error| match error {
+                Error::Io(source) => Error::CommandSpawn {
+                    key: key.to_string(),
+                    exec: exec.clone(),
+                    source,
+                },
+                error => error,
+            },
+        )?;
+        let invalid = |message: String| Error::PluginResponse {
+            key: key.to_string(),
+            plugin: plugin.to_string(),
+            message,
+        };
+        let response = match serde_json::from_slice::<PluginResponse>(&output.stdout) {
+            Ok(response) => response,
+            // A plugin that crashed before replying is reported like any command
+            Err(_) if !output.status.success() => {
+                return Err(Error::CommandFailed {
+                    key: key.to_string(),
+                    code: output.status.code(),
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                });
+            }
+            Err(error) => return Err(invalid(error.to_string())),
+        };
+        if response.protocol != PROTOCOL_VERSION {
+            return Err(invalid(format!(
+                "plugin speaks protocol version {}, expected {}",
+                response.protocol, PROTOCOL_VERSION
+            )));
+        }
+        handle_stderr(
+            key,
+            &output.stderr,
+            config.stderr.unwrap_or(StderrPolicy::Warn),
+            &mut std::io::stderr(),
+        )?;
+        match response {
+            PluginResponse {
+                error: Some(message),
+                metadata,
+                ..
+            } => Err(Error::PluginFailed {
+                key: key.to_string(),
+                plugin: plugin.to_string(),
+                message,
+                metadata,
+            }),
+            _ if !output.status.success() => Err(Error::CommandFailed {
+                key: key.to_string(),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }),
+            PluginResponse {
+                value: Some(value), ..
+            } => Ok(value),
+            _ => Err(invalid("expected a 'value' or an 'error'".to_string())),
+        }
+    }
+}
+
+/// The name after `plugin:` in the entry's `source`
+fn plugin_name(config: &ConfigValueSource) -> &str {
+    config.source.split_once(':').map_or("", |(_, name)| name)
+}
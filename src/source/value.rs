@@ -0,0 +1,24 @@
+use super::{missing_field, ConfigSource, SourceContext};
+use crate::config::ConfigValueSource;
+use crate::error::Result;
+
+/// A literal value given by the entry's `value` field
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ValueSource;
+
+impl ConfigSource for ValueSource {
+    fn fields(&self) -> &[&str] {
+        &["value"]
+    }
+
+    fn required_fields(&self) -> &[&str] {
+        &["value"]
+    }
+
+    fn fetch(&self, config: &ConfigValueSource, context: &SourceContext) -> Result<String> {
+        config
+            .value
+            .clone()
+            .ok_or_else(|| missing_field(context.key, "value"))
+    }
+}
@@ -0,0 +1,184 @@
+//! Transforms applied, in order, to a value after it is fetched and trimmed.
+
+use crate::error::{Error, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Characters left as-is by `url-encode`: the unreserved characters of RFC 3986
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A single step of an entry's `transforms` list
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    /// Strip leading and trailing whitespace
+    Trim,
+    /// Decode standard base64
+    Base64Decode,
+    /// Encode as standard base64
+    Base64Encode,
+    /// Convert to upper case
+    Upper,
+    /// Convert to lower case
+    Lower,
+    /// Keep the first capture group of the first match of a regular expression,
+    /// or the whole match if it has no groups
+    Regex(String),
+    /// Replace every occurrence of a string
+    Replace(Replace),
+    /// Add text to the start of the value
+    Prefix(String),
+    /// Add text to the end of the value
+    Suffix(String),
+    /// Percent-encode everything but unreserved characters
+    UrlEncode,
+}
+
+/// The arguments of a `replace` transform
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Replace {
+    pub from: String,
+    pub to: String,
+}
+
+impl Transform {
+    /// The name used for this transform in config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transform::Trim => "trim",
+            Transform::Base64Decode => "base64-decode",
+            Transform::Base64Encode => "base64-encode",
+            Transform::Upper => "upper",
+            Transform::Lower => "lower",
+            Transform::Regex(_) => "regex",
+            Transform::Replace(_) => "replace",
+            Transform::Prefix(_) => "prefix",
+            Transform::Suffix(_) => "suffix",
+            Transform::UrlEncode => "url-encode",
+        }
+    }
+
+    /// Describes what is wrong with the transform's arguments, if anything
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            Transform::Regex(pattern) => Regex::new(pattern).err().map(|error| {
+                // Syntax errors span several lines with the reason on the last
+                let error = error.to_string();
+                let reason = error.lines().last().unwrap_or_default();
+                let reason = reason.strip_prefix("error: ").unwrap_or(reason);
+                format!("invalid regex '{}': {}", pattern, reason)
+            }),
+            Transform::Replace(Replace { from, .. }) if from.is_empty() => {
+                Some("'replace' needs a non-empty 'from'".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, key: &str, value: String) -> Result<String> {
+        let error = |message: String| Error::Transform {
+            key: key.to_string(),
+            transform: self.name(),
+            message,
+        };
+        Ok(match self {
+            Transform::Trim => value.trim().to_string(),
+            Transform::Base64Decode => {
+                let bytes = BASE64
+                    .decode(value.trim())
+                    .map_err(|source| error(source.to_string()))?;
+                String::from_utf8(bytes).map_err(|_| Error::NotUtf8 {
+                    key: key.to_string(),
+                })?
+            }
+            Transform::Base64Encode => BASE64.encode(value),
+            Transform::Upper => value.to_uppercase(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Regex(pattern) => {
+                let regex = Regex::new(pattern).map_err(|source| error(source.to_string()))?;
+                let captures = regex
+                    .captures(&value)
+                    .ok_or_else(|| error(format!("'{}' did not match", pattern)))?;
+                let matched = captures.get(1).or_else(|| captures.get(0));
+                matched.map_or("", |matched| matched.as_str()).to_string()
+            }
+            Transform::Replace(Replace { from, to }) => value.replace(from.as_str(), to),
+            Transform::Prefix(prefix) => format!("{}{}", prefix, value),
+            Transform::Suffix(suffix) => format!("{}{}", value, suffix),
+            Transform::UrlEncode => utf8_percent_encode(&value, UNRESERVED).to_string(),
+        })
+    }
+}
+
+/// Applies each transform to the value in turn
+pub fn apply_all(key: &str, value: String, transforms: &[Transform]) -> Result<String> {
+    transforms
+        .iter()
+        .try_fold(value, |value, transform| transform.apply(key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(transforms: &str) -> Vec<Transform> {
+        serde_json::from_str(transforms).unwrap()
+    }
+
+    #[test]
+    fn transforms_apply_in_order() {
+        let transforms = parse(
+            r#"[
+                "base64-decode",
+                { "regex": "token=(\\w+)" },
+                "upper",
+                { "replace": { "from": "A", "to": "4" } },
+                { "prefix": "Bearer " },
+                { "suffix": "!" },
+                "url-encode"
+            ]"#,
+        );
+        let value = BASE64.encode("id=1 token=abc123 exp=2");
+        assert_eq!(
+            apply_all("TOKEN", value, &transforms).unwrap(),
+            "Bearer%204BC123%21"
+        );
+        assert_eq!(
+            apply_all(
+                "TOKEN",
+                " MiXeD ".to_string(),
+                &parse(r#"["trim", "lower", "base64-encode"]"#)
+            )
+            .unwrap(),
+            "bWl4ZWQ="
+        );
+    }
+
+    #[test]
+    fn transforms_report_bad_input() {
+        let error = apply_all(
+            "TOKEN",
+            "not base64!".to_string(),
+            &parse(r#"["base64-decode"]"#),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Transform {
+                transform: "base64-decode",
+                ..
+            }
+        ));
+        assert!(parse(r#"[{ "regex": "(" }]"#)[0].problem().is_some());
+        assert!(serde_json::from_str::<Vec<Transform>>(r#"["reverse"]"#).is_err());
+    }
+}
@@ -0,0 +1,507 @@
+//! Strict checks for source config files, used by the `validate` subcommand.
+//!
+//! Unlike loading a file for resolution, which stops at the first problem,
+//! validation keeps going and reports every problem it finds. Each problem is
+//! reported with the file, line and column of the entry it belongs to.
+
+use crate::config::{ConfigValueSource, SourceFormat};
+use crate::error::{Error, Result};
+use crate::interpolate::references;
+use crate::source::SourceRegistry;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A problem found in a source config file
+pub struct Problem {
+    path: String,
+    /// 1-based line and column of the entry, when it can be found
+    position: Option<(usize, usize)>,
+    /// Key of the entry, including its profile if it belongs to one
+    key: Option<String>,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some((line, column)) = self.position {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ": {}", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Checks every entry and profile entry in a source config file against the
+/// sources in `registry`. An error is only returned when the file can't be read
+/// at all.
+pub fn validate_file(
+    path: &str,
+    format: Option<SourceFormat>,
+    registry: &SourceRegistry,
+) -> Result<Vec<Problem>> {
+    let text = std::fs::read_to_string(path).map_err(|source| Error::ReadConfig {
+        path: path.to_string(),
+        source,
+    })?;
+    let format = format.unwrap_or_else(|| SourceFormat::from_path(path));
+    Ok(validate_text(path, &text, format, registry))
+}
+
+/// Checks the text of a source config file, reporting problems against `path`
+fn validate_text(
+    path: &str,
+    text: &str,
+    format: SourceFormat,
+    registry: &SourceRegistry,
+) -> Vec<Problem> {
+    let problem = |key: Option<String>, position, message: String| Problem {
+        path: path.to_string(),
+        position,
+        key,
+        message,
+    };
+
+    // Syntax errors carry their own position, so report them as-is
+    let document: Value = match parse_document(text, format) {
+        Ok(document) => document,
+        Err(message) => return vec![problem(None, None, message)],
+    };
+    let Some(entries) = document.as_object() else {
+        return vec![problem(
+            None,
+            Some((1, 1)),
+            "expected a map of keys to entries".to_string(),
+        )];
+    };
+
+    let mut problems = Vec::new();
+    // The keys each entry references, for the base entries and each profile
+    let mut base: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let mut overrides: Vec<(&str, BTreeMap<&str, Vec<String>>)> = Vec::new();
+    for (key, entry) in entries {
+        if key == "$schema" {
+            continue;
+        }
+        if key != "profiles" {
+            base.insert(key, entry_references(key, entry));
+            for message in entry_problems(entry, registry) {
+                let position = locate(text, format, &[key]);
+                problems.push(problem(Some(key.clone()), position, message));
+            }
+            continue;
+        }
+        let Some(profiles) = entry.as_object() else {
+            let position = locate(text, format, &[key]);
+            problems.push(problem(
+                None,
+                position,
+                "'profiles' must map profile names to entries".to_string(),
+            ));
+            continue;
+        };
+        for (profile, profile_entries) in profiles {
+            let Some(profile_entries) = profile_entries.as_object() else {
+                let position = locate(text, format, &["profiles", profile]);
+                problems.push(problem(
+                    None,
+                    position,
+                    format!("profile '{}' must map keys to entries", profile),
+                ));
+                continue;
+            };
+            let mut profile_references = BTreeMap::new();
+            for (key, entry) in profile_entries {
+                profile_references.insert(key.as_str(), entry_references(key, entry));
+                for message in entry_problems(entry, registry) {
+                    let position = locate(text, format, &["profiles", profile, key]);
+                    let key = format!("{} (profile '{}')", key, profile);
+                    problems.push(problem(Some(key), position, message));
+                }
+            }
+            overrides.push((profile, profile_references));
+        }
+    }
+
+    // References are checked among the base entries, and among the base
+    // entries overlaid with each profile, as resolving with that profile would
+    for (key, message) in reference_problems(&base, |_| true) {
+        let position = locate(text, format, &[key]);
+        problems.push(problem(Some(key.to_string()), position, message));
+    }
+    for (profile, profile_references) in overrides {
+        let mut graph = base.clone();
+        graph.extend(profile_references.clone());
+        let in_profile = |key: &str| profile_references.contains_key(key);
+        for (key, message) in reference_problems(&graph, in_profile) {
+            let position = locate(text, format, &["profiles", profile, key]);
+            let key = format!("{} (profile '{}')", key, profile);
+            problems.push(problem(Some(key), position, message));
+        }
+    }
+    problems.sort_by_key(|problem| problem.position);
+    problems
+}
+
+fn parse_document(text: &str, format: SourceFormat) -> Result<Value, String> {
+    match format {
+        SourceFormat::Json => serde_json::from_str(text).map_err(|error| error.to_string()),
+        SourceFormat::Yaml => serde_yaml::from_str(text).map_err(|error| error.to_string()),
+        SourceFormat::Toml => toml::from_str(text).map_err(|error| error.to_string()),
+    }
+}
+
+/// Lists everything wrong with a single entry
+fn entry_problems(entry: &Value, registry: &SourceRegistry) -> Vec<String> {
+    if !entry.is_object() {
+        return vec!["expected an entry with a 'source' field".to_string()];
+    }
+    match ConfigValueSource::deserialize(entry) {
+        Ok(config) => registry.problems(&config),
+        Err(error) => vec![error.to_string()],
+    }
+}
+
+/// The keys an entry references, or none if it is too broken to tell
+fn entry_references(key: &str, entry: &Value) -> Vec<String> {
+    ConfigValueSource::deserialize(entry)
+        .ok()
+        .and_then(|config| references(key, &config).ok())
+        .unwrap_or_default()
+}
+
+/// Lists references to keys missing from `graph`, which maps each key to the
+/// keys it references, and the cycles among its keys. Only problems with keys
+/// for which `report` is true are listed, each cycle under the first such key
+/// in it.
+fn reference_problems<'a>(
+    graph: &BTreeMap<&'a str, Vec<String>>,
+    report: impl Fn(&str) -> bool,
+) -> Vec<(&'a str, String)> {
+    let mut problems = Vec::new();
+    for (key, names) in graph {
+        if !report(key) {
+            continue;
+        }
+        for name in names {
+            if !graph.contains_key(name.as_str()) {
+                problems.push((*key, format!("references unknown key '{}'", name)));
+            }
+        }
+    }
+    let mut visited = BTreeSet::new();
+    for key in graph.keys() {
+        let mut path = Vec::new();
+        find_cycles(graph, key, &mut path, &mut visited, &mut |cycle| {
+            // Start the cycle at a reported key, so that it reads from there
+            let Some(start) = cycle.iter().position(|key| report(key)) else {
+                return;
+            };
+            let mut keys: Vec<&str> = cycle[start..]
+                .iter()
+                .chain(&cycle[..start])
+                .copied()
+                .collect();
+            keys.push(keys[0]);
+            let message = format!(
+                "keys reference each other in a cycle: {}",
+                keys.join(" -> ")
+            );
+            problems.push((keys[0], message));
+        });
+    }
+    problems
+}
+
+/// Walks the keys `key` references depth first, calling `found` with the keys
+/// of each cycle it comes across. `path` holds the keys being walked.
+fn find_cycles<'a>(
+    graph: &BTreeMap<&'a str, Vec<String>>,
+    key: &'a str,
+    path: &mut Vec<&'a str>,
+    visited: &mut BTreeSet<&'a str>,
+    found: &mut impl FnMut(&[&'a str]),
+) {
+    if let Some(start) = path.iter().position(|walking| *walking == key) {
+        found(&path[start..]);
+        return;
+    }
+    if !visited.insert(key) {
+        return;
+    }
+    path.push(key);
+    for name in &graph[key] {
+        if let Some((name, _)) = graph.get_key_value(name.as_str()) {
+            find_cycles(graph, name, path, visited, found);
+        }
+    }
+    path.pop();
+}
+
+/// Finds the line and column of a nested key, such as `["profiles", "prod",
+/// "DB_HOST"]`. TOML keys come from the parser's spans. JSON and YAML keys are
+/// found by searching for each segment of the path in key position after the
+/// previous one, skipping YAML comments.
+fn locate(text: &str, format: SourceFormat, path: &[&str]) -> Option<(usize, usize)> {
+    let offset = match format {
+        SourceFormat::Json => locate_json(text, path)?,
+        SourceFormat::Yaml => locate_yaml(text, path)?,
+        SourceFormat::Toml => locate_toml(text, path)?,
+    };
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line = text[..offset].matches('\n').count() + 1;
+    let column = text[line_start..offset].chars().count() + 1;
+    Some((line, column))
+}
+
+/// Finds each segment as a quoted string followed by `:`, directly inside the
+/// object of the segment before it. Nesting is tracked outside of strings, so
+/// keys of nested maps such as a `cmd` entry's `env` never match.
+fn locate_json(text: &str, path: &[&str]) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut found = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                // The object of the last segment found has ended
+                if depth == found + 1 && found > 0 {
+                    return None;
+                }
+                depth -= 1;
+            }
+            b'"' => {
+                let start = index;
+                index += 1;
+                while *bytes.get(index)? != b'"' {
+                    index += if bytes[index] == b'\\' { 2 } else { 1 };
+                }
+                let is_key = text[index + 1..].trim_start().starts_with(':');
+                if depth == found + 1 && is_key {
+                    let key: String = serde_json::from_str(&text[start..=index]).ok()?;
+                    if key == path[found] {
+                        found += 1;
+                        if found == path.len() {
+                            return Some(start);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Finds each segment at the start of a line, at the indentation of the
+/// document's top-level keys or of the first line of the segment before it's
+/// block, and before the end of that block
+fn locate_yaml(text: &str, path: &[&str]) -> Option<usize> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let body = content.trim_start_matches(' ');
+        if !body.is_empty() && !body.starts_with('#') && body != "---" {
+            lines.push((start, content.len() - body.len(), body));
+        }
+        start += line.len();
+    }
+    let mut found: Option<(usize, usize)> = None;
+    let mut index = 0;
+    for segment in path {
+        let &(_, block_indent, _) = lines.get(index)?;
+        let mut located = None;
+        while let Some(&(start, indent, body)) = lines.get(index) {
+            index += 1;
+            if let Some((_, parent_indent)) = found {
+                if indent <= parent_indent {
+                    return None;
+                }
+            }
+            if indent == block_indent && is_yaml_key(body, segment) {
+                located = Some((start + indent, indent));
+                break;
+            }
+        }
+        found = Some(located?);
+    }
+    found.map(|(offset, _)| offset)
+}
+
+/// Whether a line, without its indentation, starts with `key:`
+fn is_yaml_key(body: &str, key: &str) -> bool {
+    let rest = [
+        format!("\"{}\"", key),
+        format!("'{}'", key),
+        key.to_string(),
+    ]
+    .iter()
+    .find_map(|written| body.strip_prefix(written.as_str()));
+    let Some(rest) = rest else {
+        return false;
+    };
+    let Some(value) = rest.trim_start_matches([' ', '\t']).strip_prefix(':') else {
+        return false;
+    };
+    value.is_empty() || value.starts_with([' ', '\t'])
+}
+
+/// Uses the span of the last key in the path
+fn locate_toml(text: &str, path: &[&str]) -> Option<usize> {
+    let document = toml_edit::ImDocument::parse(text).ok()?;
+    let mut table: &dyn toml_edit::TableLike = document.as_table();
+    let mut span = None;
+    for segment in path {
+        let (key, item) = table.get_key_value(segment)?;
+        span = key.span();
+        if let Some(nested) = item.as_table_like() {
+            table = nested;
+        }
+    }
+    span.map(|span| span.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(text: &str, format: SourceFormat) -> Vec<String> {
+        validate_text("config", text, format, &SourceRegistry::default())
+            .iter()
+            .map(Problem::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn json_problems_point_at_keys() {
+        let text = r#"{
+  "NAME": { "source": "value", "value": "TOKEN" },
+  "TOKEN": { "source": "value", "exec": "true" },
+  "profiles": {
+    "prod": {
+      "TOKEN" : { "source": "vault" }
+    }
+  }
+}"#;
+        assert_eq!(
+            validate(text, SourceFormat::Json),
+            vec![
+                "config:3:3: TOKEN: missing field 'value' required by 'value' sources",
+                "config:3:3: TOKEN: field 'exec' is only used by 'cmd' sources",
+                "config:6:7: TOKEN (profile 'prod'): unknown source 'vault', \
+                 expected one of: cmd, env, file, value, plugin:<name>",
+            ]
+        );
+    }
+
+    #[test]
+    fn yaml_problems_skip_comments_and_values() {
+        let text = "\
+# TOKEN comes from the vault in production
+NAME:
+  source: value
+  value: TOKEN
+TOKEN: { source: env, path: token.txt }
+profiles:
+  prod:
+    NAME: { source: value, value: TOKEN }
+    'TOKEN':
+      source: file
+";
+        assert_eq!(
+            validate(text, SourceFormat::Yaml),
+            vec![
+                "config:5:1: TOKEN: field 'path' is only used by 'file' sources",
+                "config:9:5: TOKEN (profile 'prod'): missing field 'path' required by 'file' sources",
+            ]
+        );
+    }
+
+    #[test]
+    fn toml_problems_use_key_spans() {
+        let text = r#"# TOKEN comes from the vault in production
+NAME = { source = "value", value = "TOKEN" }
+TOKEN = { source = "cmd", exec = "vault", timeout = -1 }
+
+[profiles.prod.TOKEN]
+source = "value"
+"#;
+        assert_eq!(
+            validate(text, SourceFormat::Toml),
+            vec![
+                "config:3:1: TOKEN: invalid timeout -1",
+                "config:5:16: TOKEN (profile 'prod'): missing field 'value' required by 'value' sources",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_maps_reusing_a_key_are_skipped() {
+        let json = r#"{
+  "A": { "source": "cmd", "exec": "aws", "env": { "B": "1" } },
+  "B": { "source": "cmd" }
+}"#;
+        assert_eq!(
+            validate(json, SourceFormat::Json),
+            vec!["config:3:3: B: missing field 'exec' required by 'cmd' sources"]
+        );
+        let yaml = "\
+A:
+  source: cmd
+  exec: aws
+  env:
+    B: '1'
+B:
+  source: cmd
+";
+        assert_eq!(
+            validate(yaml, SourceFormat::Yaml),
+            vec!["config:6:1: B: missing field 'exec' required by 'cmd' sources"]
+        );
+    }
+
+    #[test]
+    fn references_are_checked_across_the_file() {
+        let text = r#"{
+  "A": { "source": "value", "value": "${TYPO}" },
+  "L": { "source": "value", "value": "${R}" },
+  "R": { "source": "value", "value": "${L}" },
+  "HOST": { "source": "value", "value": "db" },
+  "URL": { "source": "value", "value": "${HOST}:${env:PORT}" },
+  "profiles": {
+    "prod": {
+      "HOST": { "source": "value", "value": "${URL}" },
+      "B": { "source": "value", "value": "${MISSING}" },
+      "A": { "source": "value", "value": "fixed" }
+    }
+  }
+}"#;
+        assert_eq!(
+            validate(text, SourceFormat::Json),
+            vec![
+                "config:2:3: A: references unknown key 'TYPO'",
+                "config:3:3: L: keys reference each other in a cycle: L -> R -> L",
+                "config:9:7: HOST (profile 'prod'): keys reference each other in a cycle: \
+                 HOST -> URL -> HOST",
+                "config:10:7: B (profile 'prod'): references unknown key 'MISSING'",
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_files_are_reported_once() {
+        assert_eq!(
+            validate("[1, 2]", SourceFormat::Json),
+            vec!["config:1:1: expected a map of keys to entries"]
+        );
+        assert_eq!(validate("{", SourceFormat::Json).len(), 1);
+    }
+}